@@ -1,14 +1,29 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
 
+use egui::Color32;
 use strum_macros::EnumIter;
 
 #[derive(Debug, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Algorithm {
     AverageDithering,
     ErrorDiffusionDithering,
+    RiemersmaDithering,
     OrderedDitheringRandom,
     OrderedDitheringRelative,
+    OrderedDitheringBlueNoise,
+    Halftone,
+    PatternDithering,
     PopularityAlgorithm,
+    MedianCut,
+    Octree,
+    KMeans,
+    Wu,
+    NeuQuant,
+    FixedPalette,
 }
 
 impl Display for Algorithm {
@@ -16,50 +31,828 @@ impl Display for Algorithm {
         match self {
             Algorithm::AverageDithering => write!(f, "Average Dithering"),
             Algorithm::ErrorDiffusionDithering => write!(f, "Error Diffusion Dithering"),
+            Algorithm::RiemersmaDithering => write!(f, "Riemersma Dithering"),
             Algorithm::OrderedDitheringRandom => write!(f, "Ordered Dithering Random"),
             Algorithm::OrderedDitheringRelative => write!(f, "Ordered Dithering Relative"),
+            Algorithm::OrderedDitheringBlueNoise => write!(f, "Ordered Dithering Blue Noise"),
+            Algorithm::Halftone => write!(f, "Halftone"),
+            Algorithm::PatternDithering => write!(f, "Pattern Dithering"),
             Algorithm::PopularityAlgorithm => write!(f, "Popularity Algorithm"),
+            Algorithm::MedianCut => write!(f, "Median Cut"),
+            Algorithm::Octree => write!(f, "Octree"),
+            Algorithm::KMeans => write!(f, "K-Means"),
+            Algorithm::Wu => write!(f, "Wu's Algorithm"),
+            Algorithm::NeuQuant => write!(f, "NeuQuant"),
+            Algorithm::FixedPalette => write!(f, "Fixed Palette"),
         }
     }
 }
 
 pub enum AlgorithmType {
     Dithering,
+    ErrorDiffusion,
+    Riemersma,
+    OrderedDithering,
+    RandomOrderedDithering,
+    BlueNoiseDithering,
+    Halftone,
+    PatternDithering,
     Popularity,
+    MedianCut,
+    Octree,
+    KMeans,
+    Wu,
+    NeuQuant,
+    FixedPalette,
 }
 
 impl From<Algorithm> for AlgorithmType {
     fn from(value: Algorithm) -> Self {
         match value {
             Algorithm::AverageDithering => AlgorithmType::Dithering,
-            Algorithm::ErrorDiffusionDithering => AlgorithmType::Dithering,
-            Algorithm::OrderedDitheringRandom => AlgorithmType::Dithering,
-            Algorithm::OrderedDitheringRelative => AlgorithmType::Dithering,
+            Algorithm::ErrorDiffusionDithering => AlgorithmType::ErrorDiffusion,
+            Algorithm::RiemersmaDithering => AlgorithmType::Riemersma,
+            Algorithm::OrderedDitheringRandom => AlgorithmType::RandomOrderedDithering,
+            Algorithm::OrderedDitheringRelative => AlgorithmType::OrderedDithering,
+            Algorithm::OrderedDitheringBlueNoise => AlgorithmType::BlueNoiseDithering,
+            Algorithm::Halftone => AlgorithmType::Halftone,
+            Algorithm::PatternDithering => AlgorithmType::PatternDithering,
             Algorithm::PopularityAlgorithm => AlgorithmType::Popularity,
+            Algorithm::MedianCut => AlgorithmType::MedianCut,
+            Algorithm::Octree => AlgorithmType::Octree,
+            Algorithm::KMeans => AlgorithmType::KMeans,
+            Algorithm::Wu => AlgorithmType::Wu,
+            Algorithm::NeuQuant => AlgorithmType::NeuQuant,
+            Algorithm::FixedPalette => AlgorithmType::FixedPalette,
         }
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DitheringParameters {
     pub k_r: u8,
     pub k_g: u8,
     pub k_b: u8,
+    // Levels are chosen so that their average matches the original color in linear light, instead of in sRGB
+    pub linear_light: bool,
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+// Threshold matrices and levels need at least two levels in each channel
+impl Default for DitheringParameters {
+    fn default() -> Self {
+        Self {
+            k_r: 2,
+            k_g: 2,
+            k_b: 2,
+            linear_light: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ErrorDiffusionKernel {
+    #[default]
+    FloydSteinberg,
+    FalseFloydSteinberg,
+    JarvisJudiceNinke,
+    Stucki,
+    Burkes,
+    Sierra,
+    TwoRowSierra,
+    SierraLite,
+    Atkinson,
+}
+
+impl Display for ErrorDiffusionKernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorDiffusionKernel::FloydSteinberg => write!(f, "Floyd-Steinberg"),
+            ErrorDiffusionKernel::FalseFloydSteinberg => write!(f, "False Floyd-Steinberg"),
+            ErrorDiffusionKernel::JarvisJudiceNinke => write!(f, "Jarvis-Judice-Ninke"),
+            ErrorDiffusionKernel::Stucki => write!(f, "Stucki"),
+            ErrorDiffusionKernel::Burkes => write!(f, "Burkes"),
+            ErrorDiffusionKernel::Sierra => write!(f, "Sierra"),
+            ErrorDiffusionKernel::TwoRowSierra => write!(f, "Two-Row Sierra"),
+            ErrorDiffusionKernel::SierraLite => write!(f, "Sierra Lite"),
+            ErrorDiffusionKernel::Atkinson => write!(f, "Atkinson"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ErrorDiffusionParameters {
+    pub dithering: DitheringParameters,
+    pub kernel: ErrorDiffusionKernel,
+    // Scan every other row right to left to avoid directional artifacts
+    pub serpentine: bool,
+    // Maximum absolute error accumulated in each channel of a pixel, `None` disables clamping
+    pub error_clamp: Option<u8>,
+    pub target: DitheringTarget,
+}
+
+#[derive(Debug, Clone)]
+pub struct RiemersmaParameters {
+    pub dithering: DitheringParameters,
+    pub target: DitheringTarget,
+    // Number of last errors which are diffused to the next pixel
+    pub queue_length: u8,
+    // Weight of the oldest error in the queue relative to the newest one
+    pub ratio: f32,
+}
+
+impl Default for RiemersmaParameters {
+    fn default() -> Self {
+        Self {
+            dithering: DitheringParameters::default(),
+            target: DitheringTarget::default(),
+            queue_length: 16,
+            ratio: 1.0 / 16.0,
+        }
+    }
+}
+
+// Float parameters are compared bitwise, so that parameters can be used as a cache key
+impl PartialEq for RiemersmaParameters {
+    fn eq(&self, other: &Self) -> bool {
+        self.dithering == other.dithering
+            && self.target == other.target
+            && self.queue_length == other.queue_length
+            && self.ratio.to_bits() == other.ratio.to_bits()
+    }
+}
+
+impl Eq for RiemersmaParameters {}
+
+impl Hash for RiemersmaParameters {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.dithering.hash(state);
+        self.target.hash(state);
+        self.queue_length.hash(state);
+        self.ratio.to_bits().hash(state);
+    }
+}
+
+// Size of the Bayer matrix, `Auto` picks the smallest matrix with enough thresholds for the number of levels
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum BayerMatrixSize {
+    #[default]
+    Auto,
+    Size2,
+    Size3,
+    Size4,
+    Size6,
+    Size8,
+    Size12,
+    Size16,
+    Size24,
+    Size32,
+    Size48,
+    Size64,
+    Size96,
+    Size128,
+    Size192,
+    Size256,
+}
+
+impl BayerMatrixSize {
+    pub fn side(&self) -> Option<usize> {
+        match self {
+            BayerMatrixSize::Auto => None,
+            BayerMatrixSize::Size2 => Some(2),
+            BayerMatrixSize::Size3 => Some(3),
+            BayerMatrixSize::Size4 => Some(4),
+            BayerMatrixSize::Size6 => Some(6),
+            BayerMatrixSize::Size8 => Some(8),
+            BayerMatrixSize::Size12 => Some(12),
+            BayerMatrixSize::Size16 => Some(16),
+            BayerMatrixSize::Size24 => Some(24),
+            BayerMatrixSize::Size32 => Some(32),
+            BayerMatrixSize::Size48 => Some(48),
+            BayerMatrixSize::Size64 => Some(64),
+            BayerMatrixSize::Size96 => Some(96),
+            BayerMatrixSize::Size128 => Some(128),
+            BayerMatrixSize::Size192 => Some(192),
+            BayerMatrixSize::Size256 => Some(256),
+        }
+    }
+}
+
+impl Display for BayerMatrixSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.side() {
+            Some(n) => write!(f, "{}x{}", n, n),
+            None => write!(f, "Auto"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct OrderedDitheringParameters {
+    pub dithering: DitheringParameters,
+    pub target: DitheringTarget,
+    pub matrix_size: BayerMatrixSize,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct RandomOrderedDitheringParameters {
+    pub dithering: DitheringParameters,
+    pub target: DitheringTarget,
+    pub matrix_size: BayerMatrixSize,
+    pub seed: u64,
+}
+
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum BlueNoiseSize {
+    Size16,
+    Size32,
+    #[default]
+    Size64,
+    Size128,
+}
+
+impl BlueNoiseSize {
+    pub fn side(&self) -> usize {
+        match self {
+            BlueNoiseSize::Size16 => 16,
+            BlueNoiseSize::Size32 => 32,
+            BlueNoiseSize::Size64 => 64,
+            BlueNoiseSize::Size128 => 128,
+        }
+    }
+}
+
+impl Display for BlueNoiseSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.side(), self.side())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct BlueNoiseDitheringParameters {
+    pub dithering: DitheringParameters,
+    pub target: DitheringTarget,
+    pub size: BlueNoiseSize,
+}
+
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum HalftoneSpotFunction {
+    #[default]
+    Round,
+    Elliptical,
+    Line,
+}
+
+impl Display for HalftoneSpotFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HalftoneSpotFunction::Round => write!(f, "Round"),
+            HalftoneSpotFunction::Elliptical => write!(f, "Elliptical"),
+            HalftoneSpotFunction::Line => write!(f, "Line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HalftoneParameters {
+    pub dithering: DitheringParameters,
+    pub target: DitheringTarget,
+    pub spot_function: HalftoneSpotFunction,
+    // Screen frequency in cells per inch
+    pub frequency: u16,
+    // Resolution of the image in pixels per inch
+    pub dpi: u16,
+    // Screen angles in degrees for red, green, blue and black (K) channels
+    pub angles: [u16; 4],
+}
+
+impl Default for HalftoneParameters {
+    fn default() -> Self {
+        Self {
+            dithering: DitheringParameters::default(),
+            target: DitheringTarget::default(),
+            spot_function: HalftoneSpotFunction::default(),
+            frequency: 50,
+            dpi: 300,
+            // Classic cyan, magenta, yellow and black angles, cyan, magenta and yellow are complementary
+            // to red, green and blue
+            angles: [15, 75, 0, 45],
+        }
+    }
+}
+
+// Metric used when searching for the closest palette color
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum DistanceMetric {
+    #[default]
+    RgbEuclidean,
+    Redmean,
+    Cie76,
+    Cie94,
+    Ciede2000,
+    OkLab,
+}
+
+impl Display for DistanceMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DistanceMetric::RgbEuclidean => write!(f, "RGB Euclidean"),
+            DistanceMetric::Redmean => write!(f, "Redmean"),
+            DistanceMetric::Cie76 => write!(f, "CIE76"),
+            DistanceMetric::Cie94 => write!(f, "CIE94"),
+            DistanceMetric::Ciede2000 => write!(f, "CIEDE2000"),
+            DistanceMetric::OkLab => write!(f, "OKLab Euclidean"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct PopularityParameters {
     pub k: usize,
+    pub metric: DistanceMetric,
+}
+
+impl Default for PopularityParameters {
+    fn default() -> Self {
+        Self {
+            k: 16,
+            metric: DistanceMetric::default(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct MedianCutParameters {
+    pub k: usize,
+    pub metric: DistanceMetric,
+}
+
+impl Default for MedianCutParameters {
+    fn default() -> Self {
+        Self {
+            k: 16,
+            metric: DistanceMetric::default(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct OctreeParameters {
+    pub k: usize,
+    pub max_depth: u8,
+    // With RGB Euclidean metric pixels are mapped by walking down the tree
+    pub metric: DistanceMetric,
+}
+
+impl Default for OctreeParameters {
+    fn default() -> Self {
+        Self {
+            k: 16,
+            // Full depth, leaves distinguish every 8-bit color
+            max_depth: 8,
+            metric: DistanceMetric::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum KMeansInitialization {
+    Random,
+    #[default]
+    KMeansPlusPlus,
+    Popularity,
+}
+
+impl Display for KMeansInitialization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KMeansInitialization::Random => write!(f, "Random"),
+            KMeansInitialization::KMeansPlusPlus => write!(f, "K-Means++"),
+            KMeansInitialization::Popularity => write!(f, "Popularity Algorithm"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KMeansParameters {
+    pub k: usize,
+    pub max_iterations: u32,
+    // Iterations stop when no centroid moved further than this distance (in RGB units)
+    pub convergence_threshold: f32,
+    pub seed: u64,
+    pub initialization: KMeansInitialization,
+    pub metric: DistanceMetric,
+}
+
+impl Default for KMeansParameters {
+    fn default() -> Self {
+        Self {
+            k: 16,
+            max_iterations: 20,
+            convergence_threshold: 0.5,
+            seed: 0,
+            initialization: KMeansInitialization::default(),
+            metric: DistanceMetric::default(),
+        }
+    }
+}
+
+// Float parameters are compared bitwise, so that parameters can be used as a cache key
+impl PartialEq for KMeansParameters {
+    fn eq(&self, other: &Self) -> bool {
+        self.k == other.k
+            && self.max_iterations == other.max_iterations
+            && self.convergence_threshold.to_bits() == other.convergence_threshold.to_bits()
+            && self.seed == other.seed
+            && self.initialization == other.initialization
+            && self.metric == other.metric
+    }
+}
+
+impl Eq for KMeansParameters {}
+
+impl Hash for KMeansParameters {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.k.hash(state);
+        self.max_iterations.hash(state);
+        self.convergence_threshold.to_bits().hash(state);
+        self.seed.hash(state);
+        self.initialization.hash(state);
+        self.metric.hash(state);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct WuParameters {
+    pub k: usize,
+    pub metric: DistanceMetric,
+}
+
+impl Default for WuParameters {
+    fn default() -> Self {
+        Self {
+            k: 16,
+            metric: DistanceMetric::default(),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct NeuQuantParameters {
+    pub k: usize,
+    // Only every `sampling_factor`-th pixel is used for learning, 1 means all pixels
+    pub sampling_factor: u8,
+    pub metric: DistanceMetric,
+}
+
+impl Default for NeuQuantParameters {
+    fn default() -> Self {
+        Self {
+            k: 16,
+            sampling_factor: 1,
+            metric: DistanceMetric::default(),
+        }
+    }
+}
+
+// Palettes of retro hardware and other well-known palettes shipped with the crate
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum BuiltinPalette {
+    #[default]
+    WebSafe,
+    Cga,
+    CgaMode4Palette0Low,
+    CgaMode4Palette0High,
+    CgaMode4Palette1Low,
+    CgaMode4Palette1High,
+    CgaMode5Low,
+    CgaMode5High,
+    Ega,
+    Commodore64,
+    ZxSpectrumNormal,
+    ZxSpectrumBright,
+    Nes,
+    GameBoy,
+    GameBoyColor,
+    AmstradCpc,
+    Pico8,
+    Msx,
+    AppleII,
+    Windows16,
+    Vga256,
+}
+
+impl Display for BuiltinPalette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuiltinPalette::WebSafe => write!(f, "Web-safe (216)"),
+            BuiltinPalette::Cga => write!(f, "CGA (16)"),
+            BuiltinPalette::CgaMode4Palette0Low => write!(f, "CGA Mode 4 Palette 0 Low"),
+            BuiltinPalette::CgaMode4Palette0High => write!(f, "CGA Mode 4 Palette 0 High"),
+            BuiltinPalette::CgaMode4Palette1Low => write!(f, "CGA Mode 4 Palette 1 Low"),
+            BuiltinPalette::CgaMode4Palette1High => write!(f, "CGA Mode 4 Palette 1 High"),
+            BuiltinPalette::CgaMode5Low => write!(f, "CGA Mode 5 Low"),
+            BuiltinPalette::CgaMode5High => write!(f, "CGA Mode 5 High"),
+            BuiltinPalette::Ega => write!(f, "EGA (64)"),
+            BuiltinPalette::Commodore64 => write!(f, "Commodore 64 (Pepto)"),
+            BuiltinPalette::ZxSpectrumNormal => write!(f, "ZX Spectrum Normal"),
+            BuiltinPalette::ZxSpectrumBright => write!(f, "ZX Spectrum Bright"),
+            BuiltinPalette::Nes => write!(f, "NES (2C02)"),
+            BuiltinPalette::GameBoy => write!(f, "Game Boy (DMG)"),
+            BuiltinPalette::GameBoyColor => write!(f, "Game Boy Color (15-bit)"),
+            BuiltinPalette::AmstradCpc => write!(f, "Amstrad CPC (27)"),
+            BuiltinPalette::Pico8 => write!(f, "PICO-8"),
+            BuiltinPalette::Msx => write!(f, "MSX (TMS9918)"),
+            BuiltinPalette::AppleII => write!(f, "Apple II (Low-Res)"),
+            BuiltinPalette::Windows16 => write!(f, "Windows (16)"),
+            BuiltinPalette::Vga256 => write!(f, "VGA (256)"),
+        }
+    }
+}
+
+// Colors loaded from a file (see `Palette::into_fixed`), shared between copies of parameters
+// Content hash is computed once, so that comparing and hashing cache keys doesn't go through all colors
+#[derive(Debug, Clone)]
+pub struct ImportedPalette {
+    colors: Arc<[Color32]>,
+    hash: u64,
+}
+
+impl ImportedPalette {
+    pub fn new(colors: Vec<Color32>) -> ImportedPalette {
+        let mut hasher = DefaultHasher::new();
+        colors.hash(&mut hasher);
+        ImportedPalette {
+            colors: colors.into(),
+            hash: hasher.finish(),
+        }
+    }
+
+    pub fn colors(&self) -> &[Color32] {
+        &self.colors
+    }
+}
+
+// Colors are compared only when hashes are equal
+impl PartialEq for ImportedPalette {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+            && (Arc::ptr_eq(&self.colors, &other.colors) || self.colors == other.colors)
+    }
+}
+
+impl Eq for ImportedPalette {}
+
+impl Hash for ImportedPalette {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state);
+    }
+}
+
+// Palette which doesn't depend on the image
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum FixedPalette {
+    Builtin(BuiltinPalette),
+    Imported(ImportedPalette),
+}
+
+impl Default for FixedPalette {
+    fn default() -> Self {
+        FixedPalette::Builtin(BuiltinPalette::default())
+    }
+}
+
+impl Display for FixedPalette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixedPalette::Builtin(palette) => write!(f, "{}", palette),
+            FixedPalette::Imported(_) => write!(f, "Imported"),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct FixedPaletteParameters {
+    pub palette: FixedPalette,
+    pub metric: DistanceMetric,
+}
+
+// Dithering used when mapping an image to a fixed palette
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum FixedPaletteDithering {
+    #[default]
+    None,
+    // Same meaning as in `ErrorDiffusionParameters`
+    ErrorDiffusion {
+        kernel: ErrorDiffusionKernel,
+        serpentine: bool,
+        error_clamp: Option<u8>,
+    },
+    Ordered {
+        matrix_size: BayerMatrixSize,
+    },
+}
+
+impl Display for FixedPaletteDithering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixedPaletteDithering::None => write!(f, "None"),
+            FixedPaletteDithering::ErrorDiffusion { .. } => write!(f, "Error Diffusion"),
+            FixedPaletteDithering::Ordered { .. } => write!(f, "Ordered (Bayer)"),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct FixedPaletteMappingParameters {
+    pub palette: FixedPaletteParameters,
+    pub dithering: FixedPaletteDithering,
+}
+
+// Palette algorithm (with its parameters) used by algorithms which can work with any palette
+#[derive(Debug, EnumIter, PartialEq, Eq, Hash, Clone)]
+pub enum PaletteSource {
+    Popularity(PopularityParameters),
+    MedianCut(MedianCutParameters),
+    Octree(OctreeParameters),
+    KMeans(KMeansParameters),
+    Wu(WuParameters),
+    NeuQuant(NeuQuantParameters),
+    Fixed(FixedPaletteParameters),
+}
+
+impl Display for PaletteSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteSource::Popularity(_) => write!(f, "Popularity Algorithm"),
+            PaletteSource::MedianCut(_) => write!(f, "Median Cut"),
+            PaletteSource::Octree(_) => write!(f, "Octree"),
+            PaletteSource::KMeans(_) => write!(f, "K-Means"),
+            PaletteSource::Wu(_) => write!(f, "Wu's Algorithm"),
+            PaletteSource::NeuQuant(_) => write!(f, "NeuQuant"),
+            PaletteSource::Fixed(_) => write!(f, "Fixed Palette"),
+        }
+    }
+}
+
+impl PaletteSource {
+    // Metric used for mapping pixels to the palette
+    pub fn metric(&self) -> DistanceMetric {
+        match self {
+            PaletteSource::Popularity(params) => params.metric,
+            PaletteSource::MedianCut(params) => params.metric,
+            PaletteSource::Octree(params) => params.metric,
+            PaletteSource::KMeans(params) => params.metric,
+            PaletteSource::Wu(params) => params.metric,
+            PaletteSource::NeuQuant(params) => params.metric,
+            PaletteSource::Fixed(params) => params.metric,
+        }
+    }
+}
+
+// Colors which dithering algorithm can use in the output image
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub enum DitheringTarget {
+    // Uniform grid of `k_r`, `k_g` and `k_b` levels in each channel
+    #[default]
+    Levels,
+    Palette(PaletteSource),
+}
+
+impl Display for DitheringTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DitheringTarget::Levels => write!(f, "Levels"),
+            DitheringTarget::Palette(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum AlgorithmParameters {
     Dithering(DitheringParameters),
+    ErrorDiffusion(ErrorDiffusionParameters),
+    Riemersma(RiemersmaParameters),
+    OrderedDithering(OrderedDitheringParameters),
+    RandomOrderedDithering(RandomOrderedDitheringParameters),
+    BlueNoiseDithering(BlueNoiseDitheringParameters),
+    Halftone(HalftoneParameters),
+    PatternDithering(PatternDitheringParameters),
     Popularity(PopularityParameters),
+    MedianCut(MedianCutParameters),
+    Octree(OctreeParameters),
+    KMeans(KMeansParameters),
+    Wu(WuParameters),
+    NeuQuant(NeuQuantParameters),
+    FixedPalette(FixedPaletteMappingParameters),
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct AlgorithmCacheKey {
     pub algorithm: Algorithm,
     pub params: AlgorithmParameters,
+    pub alpha: AlphaParameters,
+}
+
+// How transparency of the image is handled, it is independent of the chosen algorithm
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum AlphaMode {
+    // Alpha of each pixel stays unchanged
+    #[default]
+    Preserve,
+    // Pixels are either fully opaque or fully transparent
+    Threshold,
+    // Alpha is dithered to `k_a` levels
+    Dither,
+    // Like `Threshold`, but all transparent pixels share a single color, which can become a separate palette entry
+    TransparentIndex,
+}
+
+impl Display for AlphaMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlphaMode::Preserve => write!(f, "Preserve"),
+            AlphaMode::Threshold => write!(f, "Binary threshold"),
+            AlphaMode::Dither => write!(f, "Dither"),
+            AlphaMode::TransparentIndex => write!(f, "Transparent palette index"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct AlphaParameters {
+    pub mode: AlphaMode,
+    // Pixels with alpha below the threshold become fully transparent
+    pub threshold: u8,
+    // Number of alpha levels used by dithering
+    pub k_a: u8,
+}
+
+impl Default for AlphaParameters {
+    fn default() -> Self {
+        Self {
+            mode: AlphaMode::default(),
+            threshold: 128,
+            k_a: 2,
+        }
+    }
+}
+
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PatternDitheringMethod {
+    #[default]
+    Knoll,
+    Yliluoma,
+}
+
+impl Display for PatternDitheringMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatternDitheringMethod::Knoll => write!(f, "Knoll"),
+            PatternDitheringMethod::Yliluoma => write!(f, "Yliluoma"),
+        }
+    }
+}
+
+// Size of the Bayer matrix, number of candidates in a mixing plan is equal to the number of its cells
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PatternSize {
+    Size2,
+    #[default]
+    Size4,
+    Size8,
+    Size16,
+}
+
+impl PatternSize {
+    pub fn side(&self) -> usize {
+        match self {
+            PatternSize::Size2 => 2,
+            PatternSize::Size4 => 4,
+            PatternSize::Size8 => 8,
+            PatternSize::Size16 => 16,
+        }
+    }
+}
+
+impl Display for PatternSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.side(), self.side())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct PatternDitheringParameters {
+    pub method: PatternDitheringMethod,
+    pub palette: PaletteSource,
+    pub size: PatternSize,
+}
+
+impl Default for PatternDitheringParameters {
+    fn default() -> Self {
+        Self {
+            method: PatternDitheringMethod::default(),
+            palette: PaletteSource::MedianCut(MedianCutParameters::default()),
+            size: PatternSize::default(),
+        }
+    }
 }
@@ -1,30 +1,80 @@
-use std::num::NonZero;
+use std::{num::NonZero, sync::Arc};
 
 use rfd::FileDialog;
 use strum::IntoEnumIterator;
 
 use crate::{
     algorithms::{
-        Algorithm, AlgorithmCacheKey, AlgorithmParameters, AlgorithmType, DitheringParameters,
-        PopularityParameters,
+        Algorithm, AlgorithmCacheKey, AlgorithmParameters, AlgorithmType, AlphaMode,
+        AlphaParameters, BayerMatrixSize, BlueNoiseDitheringParameters, BlueNoiseSize,
+        BuiltinPalette, DistanceMetric, DitheringParameters, DitheringTarget, ErrorDiffusionKernel,
+        ErrorDiffusionParameters, FixedPalette, FixedPaletteDithering,
+        FixedPaletteMappingParameters, FixedPaletteParameters, HalftoneParameters,
+        HalftoneSpotFunction, KMeansInitialization, KMeansParameters, MedianCutParameters,
+        NeuQuantParameters, OctreeParameters, OrderedDitheringParameters, PaletteSource,
+        PatternDitheringMethod, PatternDitheringParameters, PatternSize, PopularityParameters,
+        RandomOrderedDitheringParameters, RiemersmaParameters, WuParameters,
     },
+    color_quantizers::AlphaQuantizer,
     image_loader,
+    indexed_image::IndexedImage,
+    metrics::ImageMetrics,
+    palette::{Palette, PaletteFormat},
     processed_images_cache::ProcessedImagesCache,
 };
 
 const CACHE_SIZE: usize = 16;
+const DEFAULT_ERROR_CLAMP: u8 = 64;
+// Only the first colors of big palettes are drawn
+const MAX_PREVIEW_COLORS: usize = 256;
 
 pub struct App {
     previous_algorithm: Algorithm,
     algorithm: Algorithm,
     last_processed_dithering_parameters: DitheringParameters,
     current_dithering_parameters: DitheringParameters,
+    last_processed_error_diffusion_parameters: ErrorDiffusionParameters,
+    current_error_diffusion_parameters: ErrorDiffusionParameters,
+    last_processed_riemersma_parameters: RiemersmaParameters,
+    current_riemersma_parameters: RiemersmaParameters,
+    last_processed_ordered_dithering_parameters: OrderedDitheringParameters,
+    current_ordered_dithering_parameters: OrderedDitheringParameters,
+    last_processed_random_ordered_dithering_parameters: RandomOrderedDitheringParameters,
+    current_random_ordered_dithering_parameters: RandomOrderedDitheringParameters,
+    last_processed_blue_noise_dithering_parameters: BlueNoiseDitheringParameters,
+    current_blue_noise_dithering_parameters: BlueNoiseDitheringParameters,
+    last_processed_halftone_parameters: HalftoneParameters,
+    current_halftone_parameters: HalftoneParameters,
+    last_processed_pattern_dithering_parameters: PatternDitheringParameters,
+    current_pattern_dithering_parameters: PatternDitheringParameters,
     last_processed_popularity_algorithm_parameters: PopularityParameters,
     current_popularity_algorithm_parameters: PopularityParameters,
+    last_processed_median_cut_parameters: MedianCutParameters,
+    current_median_cut_parameters: MedianCutParameters,
+    last_processed_octree_parameters: OctreeParameters,
+    current_octree_parameters: OctreeParameters,
+    last_processed_k_means_parameters: KMeansParameters,
+    current_k_means_parameters: KMeansParameters,
+    last_processed_wu_parameters: WuParameters,
+    current_wu_parameters: WuParameters,
+    last_processed_neu_quant_parameters: NeuQuantParameters,
+    current_neu_quant_parameters: NeuQuantParameters,
+    last_processed_fixed_palette_parameters: FixedPaletteMappingParameters,
+    current_fixed_palette_parameters: FixedPaletteMappingParameters,
+    last_processed_alpha_parameters: AlphaParameters,
+    current_alpha_parameters: AlphaParameters,
     initial_image: Option<egui::ColorImage>,
     processed_image: Option<egui::ColorImage>,
+    // Comparison of the processed image with the initial one
+    metrics: Option<ImageMetrics>,
     processed_images_cache: ProcessedImagesCache,
     need_image_update: bool,
+    // Output is saved as paletted PNG or GIF
+    save_indexed: bool,
+    // Indexed output with too many colors is requantized instead of failing
+    requantize_indexed: bool,
+    // Message of the last failed operation, shown until dismissed
+    error_message: Option<String>,
 }
 
 impl App {
@@ -44,48 +94,454 @@ impl App {
                 }
                 match AlgorithmType::from(self.algorithm) {
                     AlgorithmType::Dithering => self.show_dithering_parameters(ui),
+                    AlgorithmType::ErrorDiffusion => self.show_error_diffusion_parameters(ui),
+                    AlgorithmType::Riemersma => self.show_riemersma_parameters(ui),
+                    AlgorithmType::OrderedDithering => self.show_ordered_dithering_parameters(ui),
+                    AlgorithmType::RandomOrderedDithering => {
+                        self.show_random_ordered_dithering_parameters(ui)
+                    }
+                    AlgorithmType::BlueNoiseDithering => {
+                        self.show_blue_noise_dithering_parameters(ui)
+                    }
+                    AlgorithmType::Halftone => self.show_halftone_parameters(ui),
+                    AlgorithmType::PatternDithering => self.show_pattern_dithering_parameters(ui),
                     AlgorithmType::Popularity => self.show_popularity_parameters(ui),
+                    AlgorithmType::MedianCut => self.show_median_cut_parameters(ui),
+                    AlgorithmType::Octree => self.show_octree_parameters(ui),
+                    AlgorithmType::KMeans => self.show_k_means_parameters(ui),
+                    AlgorithmType::Wu => self.show_wu_parameters(ui),
+                    AlgorithmType::NeuQuant => self.show_neu_quant_parameters(ui),
+                    AlgorithmType::FixedPalette => self.show_fixed_palette_parameters(ui),
                 }
+                ui.separator();
+                self.show_alpha_parameters(ui);
                 if self.initial_image.is_some() {
-                    self.show_change_image_button(ui);
+                    ui.horizontal(|ui| {
+                        self.show_change_image_button(ui);
+                        self.show_save_output_button(ui);
+                        self.show_export_palette_button(ui);
+                    });
+                    self.show_save_options(ui);
+                }
+                if let Some(metrics) = &self.metrics {
+                    ui.separator();
+                    Self::show_metrics(ui, metrics);
                 }
             });
     }
 
+    fn show_metrics(ui: &mut egui::Ui, metrics: &ImageMetrics) {
+        let channels = |values: [f64; 3]| {
+            let [r, g, b] = values.map(|value| format!("{:.2}", value));
+            format!("{} / {} / {}", r, g, b)
+        };
+        egui::Grid::new("metrics").striped(true).show(ui, |ui| {
+            ui.label("MSE (R / G / B)");
+            ui.label(channels(metrics.mse));
+            ui.end_row();
+            ui.label("PSNR (R / G / B) [dB]");
+            ui.label(channels(metrics.psnr));
+            ui.end_row();
+            ui.label("SSIM");
+            ui.label(format!("{:.4}", metrics.ssim));
+            ui.end_row();
+            ui.label("MS-SSIM");
+            ui.label(format!("{:.4}", metrics.ms_ssim));
+            ui.end_row();
+            ui.label("ΔE2000 (mean / max)");
+            ui.label(format!(
+                "{:.2} / {:.2}",
+                metrics.mean_delta_e, metrics.max_delta_e
+            ));
+            ui.end_row();
+            ui.label("Unique colors");
+            ui.label(format!("{}", metrics.unique_colors));
+            ui.end_row();
+        });
+    }
+
+    // Shows sliders for numbers of levels in each channel
+    // Returns whether any of them is being dragged and whether any of them has focus
+    fn show_levels_sliders(ui: &mut egui::Ui, params: &mut DitheringParameters) -> (bool, bool) {
+        let r_response = ui.add(egui::Slider::new(&mut params.k_r, 2..=255).text("Kr"));
+        let g_response = ui.add(egui::Slider::new(&mut params.k_g, 2..=255).text("Kg"));
+        let b_response = ui.add(egui::Slider::new(&mut params.k_b, 2..=255).text("Kb"));
+        ui.checkbox(&mut params.linear_light, "Linear light");
+
+        let any_dragging = r_response.dragged() || g_response.dragged() || b_response.dragged();
+        let any_focus = r_response.has_focus() || g_response.has_focus() || b_response.has_focus();
+        (any_dragging, any_focus)
+    }
+
+    // Shows choice between uniform levels and palette built by one of palette algorithms
+    // together with controls for the chosen target
+    fn show_dithering_target(
+        ui: &mut egui::Ui,
+        dithering: &mut DitheringParameters,
+        target: &mut DitheringTarget,
+        error_message: &mut Option<String>,
+    ) -> (bool, bool) {
+        egui::ComboBox::from_label("Target")
+            .selected_text(format!("{}", target))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(target, DitheringTarget::Levels, "Levels");
+                for source in PaletteSource::iter() {
+                    let selected = matches!(target, DitheringTarget::Palette(current)
+                        if std::mem::discriminant(current) == std::mem::discriminant(&source));
+                    if ui
+                        .selectable_label(selected, format!("{}", source))
+                        .clicked()
+                        && !selected
+                    {
+                        *target = DitheringTarget::Palette(source);
+                    }
+                }
+            });
+        match target {
+            DitheringTarget::Levels => Self::show_levels_sliders(ui, dithering),
+            DitheringTarget::Palette(source) => {
+                Self::show_palette_source_controls(ui, source, error_message)
+            }
+        }
+    }
+
+    fn show_palette_source_controls(
+        ui: &mut egui::Ui,
+        source: &mut PaletteSource,
+        error_message: &mut Option<String>,
+    ) -> (bool, bool) {
+        match source {
+            PaletteSource::Popularity(params) => Self::show_popularity_controls(ui, params),
+            PaletteSource::MedianCut(params) => Self::show_median_cut_controls(ui, params),
+            PaletteSource::Octree(params) => Self::show_octree_controls(ui, params),
+            PaletteSource::KMeans(params) => Self::show_k_means_controls(ui, params),
+            PaletteSource::Wu(params) => Self::show_wu_controls(ui, params),
+            PaletteSource::NeuQuant(params) => Self::show_neu_quant_controls(ui, params),
+            PaletteSource::Fixed(params) => {
+                Self::show_fixed_palette_controls(ui, params, error_message)
+            }
+        }
+    }
+
+    // Shows choice of palette algorithm together with its controls
+    fn show_palette_source(
+        ui: &mut egui::Ui,
+        source: &mut PaletteSource,
+        error_message: &mut Option<String>,
+    ) -> (bool, bool) {
+        egui::ComboBox::from_label("Palette")
+            .selected_text(format!("{}", source))
+            .show_ui(ui, |ui| {
+                for new_source in PaletteSource::iter() {
+                    let selected =
+                        std::mem::discriminant(source) == std::mem::discriminant(&new_source);
+                    if ui
+                        .selectable_label(selected, format!("{}", new_source))
+                        .clicked()
+                        && !selected
+                    {
+                        *source = new_source;
+                    }
+                }
+            });
+        Self::show_palette_source_controls(ui, source, error_message)
+    }
+
     fn show_dithering_parameters(&mut self, ui: &mut egui::Ui) {
         ui.vertical(|ui| {
-            let r_response = ui.add(
-                egui::Slider::new(&mut self.current_dithering_parameters.k_r, 2..=255).text("Kr"),
+            let (any_dragging, any_focus) =
+                Self::show_levels_sliders(ui, &mut self.current_dithering_parameters);
+            let values_changed =
+                self.current_dithering_parameters != self.last_processed_dithering_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_dithering_parameters = self.current_dithering_parameters;
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_error_diffusion_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_error_diffusion_parameters;
+            let (any_dragging, any_focus) = Self::show_dithering_target(
+                ui,
+                &mut params.dithering,
+                &mut params.target,
+                &mut self.error_message,
+            );
+            let (kernel_dragging, kernel_focus) = Self::show_error_diffusion_controls(
+                ui,
+                &mut params.kernel,
+                &mut params.serpentine,
+                &mut params.error_clamp,
             );
-            let g_response = ui.add(
-                egui::Slider::new(&mut self.current_dithering_parameters.k_g, 2..=255).text("Kg"),
+
+            let any_dragging = any_dragging || kernel_dragging;
+            let any_focus = any_focus || kernel_focus;
+
+            let values_changed = self.current_error_diffusion_parameters
+                != self.last_processed_error_diffusion_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_error_diffusion_parameters =
+                    self.current_error_diffusion_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_error_diffusion_controls(
+        ui: &mut egui::Ui,
+        kernel: &mut ErrorDiffusionKernel,
+        serpentine: &mut bool,
+        error_clamp: &mut Option<u8>,
+    ) -> (bool, bool) {
+        egui::ComboBox::from_label("Kernel")
+            .selected_text(format!("{}", kernel))
+            .show_ui(ui, |ui| {
+                for new_kernel in ErrorDiffusionKernel::iter() {
+                    ui.selectable_value(kernel, new_kernel, format!("{}", new_kernel));
+                }
+            });
+        ui.checkbox(serpentine, "Serpentine scanning");
+        let mut clamp_enabled = error_clamp.is_some();
+        let mut clamp_limit = error_clamp.unwrap_or(DEFAULT_ERROR_CLAMP);
+        ui.checkbox(&mut clamp_enabled, "Clamp error");
+        let clamp_response = ui.add_enabled(
+            clamp_enabled,
+            egui::Slider::new(&mut clamp_limit, 1..=255).text("Error limit"),
+        );
+        *error_clamp = clamp_enabled.then_some(clamp_limit);
+        (clamp_response.dragged(), clamp_response.has_focus())
+    }
+
+    fn show_riemersma_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_riemersma_parameters;
+            let (any_dragging, any_focus) = Self::show_dithering_target(
+                ui,
+                &mut params.dithering,
+                &mut params.target,
+                &mut self.error_message,
             );
-            let b_response = ui.add(
-                egui::Slider::new(&mut self.current_dithering_parameters.k_b, 2..=255).text("Kb"),
+            let queue_response =
+                ui.add(egui::Slider::new(&mut params.queue_length, 1..=64).text("Queue length"));
+            let ratio_response = ui.add(
+                egui::Slider::new(&mut params.ratio, 0.01..=1.0)
+                    .logarithmic(true)
+                    .text("Ratio"),
             );
 
-            let any_dragging = r_response.dragged() || g_response.dragged() || b_response.dragged();
-            let any_focus =
-                r_response.has_focus() || g_response.has_focus() || b_response.has_focus();
+            let any_dragging = any_dragging || queue_response.dragged() || ratio_response.dragged();
+            let any_focus = any_focus || queue_response.has_focus() || ratio_response.has_focus();
+
             let values_changed =
-                self.current_dithering_parameters != self.last_processed_dithering_parameters;
+                self.current_riemersma_parameters != self.last_processed_riemersma_parameters;
             if values_changed && !any_dragging && !any_focus {
-                self.last_processed_dithering_parameters = self.current_dithering_parameters;
+                self.last_processed_riemersma_parameters =
+                    self.current_riemersma_parameters.clone();
                 self.need_image_update = true;
             }
         });
     }
 
-    fn show_popularity_parameters(&mut self, ui: &mut egui::Ui) {
-        let k_response = ui.add(
-            egui::Slider::new(
-                &mut self.current_popularity_algorithm_parameters.k,
-                2..=8192,
-            )
-            .text("K"),
+    fn show_ordered_dithering_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_ordered_dithering_parameters;
+            let (any_dragging, any_focus) = Self::show_dithering_target(
+                ui,
+                &mut params.dithering,
+                &mut params.target,
+                &mut self.error_message,
+            );
+            Self::show_bayer_matrix_size(ui, &mut params.matrix_size);
+            let values_changed = self.current_ordered_dithering_parameters
+                != self.last_processed_ordered_dithering_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_ordered_dithering_parameters =
+                    self.current_ordered_dithering_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_bayer_matrix_size(ui: &mut egui::Ui, matrix_size: &mut BayerMatrixSize) {
+        egui::ComboBox::from_label("Matrix size")
+            .selected_text(format!("{}", matrix_size))
+            .show_ui(ui, |ui| {
+                for size in BayerMatrixSize::iter() {
+                    ui.selectable_value(matrix_size, size, format!("{}", size));
+                }
+            });
+    }
+
+    fn show_random_ordered_dithering_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_random_ordered_dithering_parameters;
+            let (any_dragging, any_focus) = Self::show_dithering_target(
+                ui,
+                &mut params.dithering,
+                &mut params.target,
+                &mut self.error_message,
+            );
+            Self::show_bayer_matrix_size(ui, &mut params.matrix_size);
+            let seed_response = ui.horizontal(|ui| {
+                let seed_response = ui.add(egui::DragValue::new(&mut params.seed).prefix("Seed: "));
+                if ui.button("Reseed").clicked() {
+                    params.seed = rand::random();
+                }
+                seed_response
+            });
+            let any_dragging = any_dragging || seed_response.inner.dragged();
+            let any_focus = any_focus || seed_response.inner.has_focus();
+            let values_changed = self.current_random_ordered_dithering_parameters
+                != self.last_processed_random_ordered_dithering_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_random_ordered_dithering_parameters =
+                    self.current_random_ordered_dithering_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_blue_noise_dithering_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_blue_noise_dithering_parameters;
+            let (any_dragging, any_focus) = Self::show_dithering_target(
+                ui,
+                &mut params.dithering,
+                &mut params.target,
+                &mut self.error_message,
+            );
+            egui::ComboBox::from_label("Threshold map size")
+                .selected_text(format!("{}", params.size))
+                .show_ui(ui, |ui| {
+                    for size in BlueNoiseSize::iter() {
+                        ui.selectable_value(&mut params.size, size, format!("{}", size));
+                    }
+                });
+            let values_changed = self.current_blue_noise_dithering_parameters
+                != self.last_processed_blue_noise_dithering_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_blue_noise_dithering_parameters =
+                    self.current_blue_noise_dithering_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_halftone_controls(
+        ui: &mut egui::Ui,
+        params: &mut HalftoneParameters,
+        error_message: &mut Option<String>,
+    ) -> (bool, bool) {
+        let (target_dragging, target_focus) = Self::show_dithering_target(
+            ui,
+            &mut params.dithering,
+            &mut params.target,
+            error_message,
+        );
+        egui::ComboBox::from_label("Spot function")
+            .selected_text(format!("{}", params.spot_function))
+            .show_ui(ui, |ui| {
+                for spot_function in HalftoneSpotFunction::iter() {
+                    ui.selectable_value(
+                        &mut params.spot_function,
+                        spot_function,
+                        format!("{}", spot_function),
+                    );
+                }
+            });
+        let dpi_response = ui.add(egui::Slider::new(&mut params.dpi, 72..=1200).text("Image DPI"));
+        let max_frequency = params.dpi / 2;
+        let frequency_response = ui.add(
+            egui::Slider::new(&mut params.frequency, 1..=max_frequency).text("Screen frequency"),
         );
-        let is_dragging = k_response.dragged();
-        let is_focused = k_response.has_focus();
+        let [r_angle, g_angle, b_angle, k_angle] = &mut params.angles;
+        let r_response = ui.add(egui::Slider::new(r_angle, 0..=179).text("Red angle"));
+        let g_response = ui.add(egui::Slider::new(g_angle, 0..=179).text("Green angle"));
+        let b_response = ui.add(egui::Slider::new(b_angle, 0..=179).text("Blue angle"));
+        let k_response = ui.add(egui::Slider::new(k_angle, 0..=179).text("Black angle"));
+
+        let responses = [
+            dpi_response,
+            frequency_response,
+            r_response,
+            g_response,
+            b_response,
+            k_response,
+        ];
+        let any_dragging = target_dragging || responses.iter().any(|response| response.dragged());
+        let any_focus = target_focus || responses.iter().any(|response| response.has_focus());
+        (any_dragging, any_focus)
+    }
+
+    fn show_halftone_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let (any_dragging, any_focus) = Self::show_halftone_controls(
+                ui,
+                &mut self.current_halftone_parameters,
+                &mut self.error_message,
+            );
+            let values_changed =
+                self.current_halftone_parameters != self.last_processed_halftone_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_halftone_parameters = self.current_halftone_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_pattern_dithering_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_pattern_dithering_parameters;
+            egui::ComboBox::from_label("Method")
+                .selected_text(format!("{}", params.method))
+                .show_ui(ui, |ui| {
+                    for method in PatternDitheringMethod::iter() {
+                        ui.selectable_value(&mut params.method, method, format!("{}", method));
+                    }
+                });
+            egui::ComboBox::from_label("Pattern size")
+                .selected_text(format!("{}", params.size))
+                .show_ui(ui, |ui| {
+                    for size in PatternSize::iter() {
+                        ui.selectable_value(&mut params.size, size, format!("{}", size));
+                    }
+                });
+            let (any_dragging, any_focus) =
+                Self::show_palette_source(ui, &mut params.palette, &mut self.error_message);
+            let values_changed = self.current_pattern_dithering_parameters
+                != self.last_processed_pattern_dithering_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_pattern_dithering_parameters =
+                    self.current_pattern_dithering_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_distance_metric(ui: &mut egui::Ui, metric: &mut DistanceMetric) {
+        egui::ComboBox::from_label("Distance metric")
+            .selected_text(format!("{}", metric))
+            .show_ui(ui, |ui| {
+                for new_metric in DistanceMetric::iter() {
+                    ui.selectable_value(metric, new_metric, format!("{}", new_metric));
+                }
+            });
+    }
+
+    fn show_popularity_controls(
+        ui: &mut egui::Ui,
+        params: &mut PopularityParameters,
+    ) -> (bool, bool) {
+        let k_response = ui.add(egui::Slider::new(&mut params.k, 2..=8192).text("K"));
+        Self::show_distance_metric(ui, &mut params.metric);
+        (k_response.dragged(), k_response.has_focus())
+    }
+
+    fn show_popularity_parameters(&mut self, ui: &mut egui::Ui) {
+        let (is_dragging, is_focused) =
+            Self::show_popularity_controls(ui, &mut self.current_popularity_algorithm_parameters);
         let values_changed = self.current_popularity_algorithm_parameters
             != self.last_processed_popularity_algorithm_parameters;
         if values_changed && !is_dragging && !is_focused {
@@ -95,6 +551,268 @@ impl App {
         }
     }
 
+    fn show_median_cut_controls(
+        ui: &mut egui::Ui,
+        params: &mut MedianCutParameters,
+    ) -> (bool, bool) {
+        let k_response = ui.add(egui::Slider::new(&mut params.k, 2..=8192).text("K"));
+        Self::show_distance_metric(ui, &mut params.metric);
+        (k_response.dragged(), k_response.has_focus())
+    }
+
+    fn show_median_cut_parameters(&mut self, ui: &mut egui::Ui) {
+        let (is_dragging, is_focused) =
+            Self::show_median_cut_controls(ui, &mut self.current_median_cut_parameters);
+        let values_changed =
+            self.current_median_cut_parameters != self.last_processed_median_cut_parameters;
+        if values_changed && !is_dragging && !is_focused {
+            self.last_processed_median_cut_parameters = self.current_median_cut_parameters;
+            self.need_image_update = true;
+        }
+    }
+
+    fn show_octree_controls(ui: &mut egui::Ui, params: &mut OctreeParameters) -> (bool, bool) {
+        let k_response = ui.add(egui::Slider::new(&mut params.k, 2..=8192).text("K"));
+        Self::show_distance_metric(ui, &mut params.metric);
+        let depth_response =
+            ui.add(egui::Slider::new(&mut params.max_depth, 1..=8).text("Max depth"));
+
+        let any_dragging = k_response.dragged() || depth_response.dragged();
+        let any_focus = k_response.has_focus() || depth_response.has_focus();
+        (any_dragging, any_focus)
+    }
+
+    fn show_octree_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let (any_dragging, any_focus) =
+                Self::show_octree_controls(ui, &mut self.current_octree_parameters);
+            let values_changed =
+                self.current_octree_parameters != self.last_processed_octree_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_octree_parameters = self.current_octree_parameters;
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_k_means_controls(ui: &mut egui::Ui, params: &mut KMeansParameters) -> (bool, bool) {
+        let k_response = ui.add(egui::Slider::new(&mut params.k, 2..=256).text("K"));
+        Self::show_distance_metric(ui, &mut params.metric);
+        let iterations_response =
+            ui.add(egui::Slider::new(&mut params.max_iterations, 1..=100).text("Max iterations"));
+        let threshold_response = ui.add(
+            egui::Slider::new(&mut params.convergence_threshold, 0.0..=10.0)
+                .text("Convergence threshold"),
+        );
+        let seed_response = ui.add(egui::DragValue::new(&mut params.seed).prefix("Seed: "));
+        egui::ComboBox::from_label("Initialization")
+            .selected_text(format!("{}", params.initialization))
+            .show_ui(ui, |ui| {
+                for initialization in KMeansInitialization::iter() {
+                    ui.selectable_value(
+                        &mut params.initialization,
+                        initialization,
+                        format!("{}", initialization),
+                    );
+                }
+            });
+
+        let any_dragging = k_response.dragged()
+            || iterations_response.dragged()
+            || threshold_response.dragged()
+            || seed_response.dragged();
+        let any_focus = k_response.has_focus()
+            || iterations_response.has_focus()
+            || threshold_response.has_focus()
+            || seed_response.has_focus();
+        (any_dragging, any_focus)
+    }
+
+    fn show_k_means_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let (any_dragging, any_focus) =
+                Self::show_k_means_controls(ui, &mut self.current_k_means_parameters);
+            let values_changed =
+                self.current_k_means_parameters != self.last_processed_k_means_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_k_means_parameters = self.current_k_means_parameters;
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_wu_controls(ui: &mut egui::Ui, params: &mut WuParameters) -> (bool, bool) {
+        let k_response = ui.add(egui::Slider::new(&mut params.k, 2..=256).text("K"));
+        Self::show_distance_metric(ui, &mut params.metric);
+        (k_response.dragged(), k_response.has_focus())
+    }
+
+    fn show_wu_parameters(&mut self, ui: &mut egui::Ui) {
+        let (is_dragging, is_focused) = Self::show_wu_controls(ui, &mut self.current_wu_parameters);
+        let values_changed = self.current_wu_parameters != self.last_processed_wu_parameters;
+        if values_changed && !is_dragging && !is_focused {
+            self.last_processed_wu_parameters = self.current_wu_parameters;
+            self.need_image_update = true;
+        }
+    }
+
+    fn show_neu_quant_controls(ui: &mut egui::Ui, params: &mut NeuQuantParameters) -> (bool, bool) {
+        let k_response = ui.add(egui::Slider::new(&mut params.k, 2..=256).text("K"));
+        Self::show_distance_metric(ui, &mut params.metric);
+        let sampling_response =
+            ui.add(egui::Slider::new(&mut params.sampling_factor, 1..=30).text("Sampling factor"));
+
+        let any_dragging = k_response.dragged() || sampling_response.dragged();
+        let any_focus = k_response.has_focus() || sampling_response.has_focus();
+        (any_dragging, any_focus)
+    }
+
+    fn show_neu_quant_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let (any_dragging, any_focus) =
+                Self::show_neu_quant_controls(ui, &mut self.current_neu_quant_parameters);
+            let values_changed =
+                self.current_neu_quant_parameters != self.last_processed_neu_quant_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_neu_quant_parameters = self.current_neu_quant_parameters;
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    // Shows button importing palette from a file together with preview of the palette
+    fn show_fixed_palette_controls(
+        ui: &mut egui::Ui,
+        params: &mut FixedPaletteParameters,
+        error_message: &mut Option<String>,
+    ) -> (bool, bool) {
+        egui::ComboBox::from_label("Fixed palette")
+            .selected_text(format!("{}", params.palette))
+            .show_ui(ui, |ui| {
+                for palette in BuiltinPalette::iter() {
+                    ui.selectable_value(
+                        &mut params.palette,
+                        FixedPalette::Builtin(palette),
+                        format!("{}", palette),
+                    );
+                }
+            });
+        if ui.button("Import palette").clicked() {
+            if let Some(path) = FileDialog::new()
+                .add_filter("Palette", &["gpl", "act", "pal", "txt", "hex"])
+                .add_filter("Image", &["png", "gif", "bmp"])
+                .pick_file()
+            {
+                match Palette::load(path) {
+                    Ok(palette) => params.palette = palette.into_fixed(),
+                    Err(e) => *error_message = Some(format!("{:#}", e)),
+                }
+            }
+        }
+        // Preview is kept in egui memory until the palette changes, as big palettes are slow to build
+        let preview_id = egui::Id::new("fixed_palette_preview");
+        let palette =
+            ui.data_mut(
+                |data| match data.get_temp::<(FixedPalette, Arc<Palette>)>(preview_id) {
+                    Some((cached, palette)) if cached == params.palette => palette,
+                    _ => {
+                        let palette = Arc::new(Palette::fixed(&params.palette));
+                        data.insert_temp(preview_id, (params.palette.clone(), palette.clone()));
+                        palette
+                    }
+                },
+            );
+        ui.label(format!("{} colors", palette.len()));
+        ui.horizontal_wrapped(|ui| {
+            ui.spacing_mut().item_spacing = egui::Vec2::splat(1.0);
+            for &color in palette.colors.iter().take(MAX_PREVIEW_COLORS) {
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::Vec2::splat(10.0), egui::Sense::hover());
+                ui.painter().rect_filled(rect, 0.0, color);
+            }
+        });
+        if palette.len() > MAX_PREVIEW_COLORS {
+            ui.label(format!("+{} more", palette.len() - MAX_PREVIEW_COLORS));
+        }
+        Self::show_distance_metric(ui, &mut params.metric);
+        (false, false)
+    }
+
+    fn show_fixed_palette_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_fixed_palette_parameters;
+            let (any_dragging, any_focus) =
+                Self::show_fixed_palette_controls(ui, &mut params.palette, &mut self.error_message);
+            egui::ComboBox::from_label("Dithering")
+                .selected_text(format!("{}", params.dithering))
+                .show_ui(ui, |ui| {
+                    for dithering in FixedPaletteDithering::iter() {
+                        let selected = std::mem::discriminant(&params.dithering)
+                            == std::mem::discriminant(&dithering);
+                        if ui
+                            .selectable_label(selected, format!("{}", dithering))
+                            .clicked()
+                            && !selected
+                        {
+                            params.dithering = dithering;
+                        }
+                    }
+                });
+            let (dithering_dragging, dithering_focus) = match &mut params.dithering {
+                FixedPaletteDithering::None => (false, false),
+                FixedPaletteDithering::ErrorDiffusion {
+                    kernel,
+                    serpentine,
+                    error_clamp,
+                } => Self::show_error_diffusion_controls(ui, kernel, serpentine, error_clamp),
+                FixedPaletteDithering::Ordered { matrix_size } => {
+                    Self::show_bayer_matrix_size(ui, matrix_size);
+                    (false, false)
+                }
+            };
+            let any_dragging = any_dragging || dithering_dragging;
+            let any_focus = any_focus || dithering_focus;
+            let values_changed = self.current_fixed_palette_parameters
+                != self.last_processed_fixed_palette_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_fixed_palette_parameters =
+                    self.current_fixed_palette_parameters.clone();
+                self.need_image_update = true;
+            }
+        });
+    }
+
+    fn show_alpha_parameters(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            let params = &mut self.current_alpha_parameters;
+            egui::ComboBox::from_label("Alpha")
+                .selected_text(format!("{}", params.mode))
+                .show_ui(ui, |ui| {
+                    for mode in AlphaMode::iter() {
+                        ui.selectable_value(&mut params.mode, mode, format!("{}", mode));
+                    }
+                });
+            let (any_dragging, any_focus) = match params.mode {
+                AlphaMode::Preserve => (false, false),
+                AlphaMode::Threshold | AlphaMode::TransparentIndex => {
+                    let response =
+                        ui.add(egui::Slider::new(&mut params.threshold, 1..=255).text("Threshold"));
+                    (response.dragged(), response.has_focus())
+                }
+                AlphaMode::Dither => {
+                    let response = ui.add(egui::Slider::new(&mut params.k_a, 2..=255).text("Ka"));
+                    (response.dragged(), response.has_focus())
+                }
+            };
+            let values_changed =
+                self.current_alpha_parameters != self.last_processed_alpha_parameters;
+            if values_changed && !any_dragging && !any_focus {
+                self.last_processed_alpha_parameters = self.current_alpha_parameters;
+                self.need_image_update = true;
+            }
+        });
+    }
+
     fn show_central_panel(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| match &self.initial_image {
             Some(initial_image) => {
@@ -144,12 +862,32 @@ impl App {
 
         ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
             ui.add_space(space_width);
-            ui.add(normal_image);
+            Self::show_image(ui, normal_image);
             ui.add_space(space_width);
-            ui.add(processed_image);
+            Self::show_image(ui, processed_image);
         });
     }
 
+    // Transparent regions of the image are shown over a checkerboard
+    fn show_image(ui: &mut egui::Ui, image: egui::Image) {
+        const CELL_SIZE: f32 = 8.0;
+
+        let size = image.calc_size(ui.available_size(), image.size());
+        let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(204));
+        let columns = (rect.width() / CELL_SIZE).ceil() as usize;
+        let rows = (rect.height() / CELL_SIZE).ceil() as usize;
+        for row in 0..rows {
+            for column in (row % 2..columns).step_by(2) {
+                let min = rect.min + egui::vec2(column as f32, row as f32) * CELL_SIZE;
+                let cell = egui::Rect::from_min_size(min, egui::Vec2::splat(CELL_SIZE));
+                painter.rect_filled(cell, 0.0, egui::Color32::from_gray(153));
+            }
+        }
+        image.paint_at(ui, rect);
+    }
+
     fn show_load_initial_image_button(&mut self, ui: &mut egui::Ui) {
         ui.vertical_centered(|ui| {
             let button_size = ui.spacing().interact_size * 2.0;
@@ -172,6 +910,169 @@ impl App {
         }
     }
 
+    fn show_save_output_button(&mut self, ui: &mut egui::Ui) {
+        let enabled = self.processed_image.is_some();
+        if ui
+            .add_enabled(enabled, egui::Button::new("Save output"))
+            .clicked()
+        {
+            self.file_dialog_save_output();
+        }
+    }
+
+    fn show_export_palette_button(&mut self, ui: &mut egui::Ui) {
+        let enabled = self.processed_image.is_some();
+        if ui
+            .add_enabled(enabled, egui::Button::new("Export palette"))
+            .clicked()
+        {
+            self.file_dialog_export_palette();
+        }
+    }
+
+    // Palette algorithm used by the current algorithm, if it uses any
+    fn current_palette_source(&self) -> Option<PaletteSource> {
+        let target_palette = |target: DitheringTarget| match target {
+            DitheringTarget::Levels => None,
+            DitheringTarget::Palette(source) => Some(source),
+        };
+        match AlgorithmType::from(self.algorithm) {
+            AlgorithmType::Dithering => None,
+            AlgorithmType::ErrorDiffusion => {
+                target_palette(self.current_error_diffusion_parameters.target.clone())
+            }
+            AlgorithmType::Riemersma => {
+                target_palette(self.current_riemersma_parameters.target.clone())
+            }
+            AlgorithmType::OrderedDithering => {
+                target_palette(self.current_ordered_dithering_parameters.target.clone())
+            }
+            AlgorithmType::RandomOrderedDithering => target_palette(
+                self.current_random_ordered_dithering_parameters
+                    .target
+                    .clone(),
+            ),
+            AlgorithmType::BlueNoiseDithering => {
+                target_palette(self.current_blue_noise_dithering_parameters.target.clone())
+            }
+            AlgorithmType::Halftone => {
+                target_palette(self.current_halftone_parameters.target.clone())
+            }
+            AlgorithmType::PatternDithering => {
+                Some(self.current_pattern_dithering_parameters.palette.clone())
+            }
+            AlgorithmType::Popularity => Some(PaletteSource::Popularity(
+                self.current_popularity_algorithm_parameters,
+            )),
+            AlgorithmType::MedianCut => {
+                Some(PaletteSource::MedianCut(self.current_median_cut_parameters))
+            }
+            AlgorithmType::Octree => Some(PaletteSource::Octree(self.current_octree_parameters)),
+            AlgorithmType::KMeans => Some(PaletteSource::KMeans(self.current_k_means_parameters)),
+            AlgorithmType::Wu => Some(PaletteSource::Wu(self.current_wu_parameters)),
+            AlgorithmType::NeuQuant => {
+                Some(PaletteSource::NeuQuant(self.current_neu_quant_parameters))
+            }
+            AlgorithmType::FixedPalette => Some(PaletteSource::Fixed(
+                self.current_fixed_palette_parameters.palette.clone(),
+            )),
+        }
+    }
+
+    // Palette chosen by the palette algorithm, algorithms without one export colors of the output
+    fn current_palette(&self) -> Option<Palette> {
+        let initial_image = self.initial_image.as_ref()?;
+        let palette = match self.current_palette_source() {
+            Some(source) => {
+                let alpha =
+                    AlphaQuantizer::quantize_alpha(self.current_alpha_parameters, initial_image);
+                let opaque_image = AlphaQuantizer::opaque_image(initial_image, &alpha);
+                Palette::build(&source, &opaque_image)
+            }
+            None => Palette::from_image(self.processed_image.as_ref()?),
+        };
+        Some(palette)
+    }
+
+    fn file_dialog_export_palette(&mut self) {
+        let Some(palette) = self.current_palette() else {
+            return;
+        };
+        let dialog = PaletteFormat::iter().fold(FileDialog::new(), |dialog, format| {
+            dialog.add_filter(format.to_string(), &[format.extension()])
+        });
+        if let Some(mut path) = dialog.set_file_name("palette.gpl").save_file() {
+            // Not every dialog appends the extension of the chosen filter
+            if path.extension().is_none() {
+                path.set_extension(PaletteFormat::default().extension());
+            }
+            if let Err(e) = palette.save(&path) {
+                self.error_message = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    fn show_save_options(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.save_indexed, "Indexed colors (PNG, GIF)");
+        ui.add_enabled(
+            self.save_indexed,
+            egui::Checkbox::new(&mut self.requantize_indexed, "Requantize to 256 colors"),
+        );
+    }
+
+    fn file_dialog_save_output(&mut self) {
+        let Some(processed_image) = &self.processed_image else {
+            return;
+        };
+        let dialog = FileDialog::new().add_filter("PNG", &["png"]);
+        let dialog = if self.save_indexed {
+            dialog.add_filter("GIF", &["gif"])
+        } else {
+            dialog
+                .add_filter("JPEG", &["jpg", "jpeg"])
+                .add_filter("BMP", &["bmp"])
+                .add_filter("WebP", &["webp"])
+                .add_filter("TIFF", &["tif", "tiff"])
+        };
+        if let Some(mut path) = dialog.set_file_name("output.png").save_file() {
+            // Not every dialog appends the extension of the chosen filter
+            if path.extension().is_none() {
+                path.set_extension("png");
+            }
+            let result = if self.save_indexed {
+                let indexed_image = if self.requantize_indexed {
+                    IndexedImage::from_color_image_requantized(processed_image)
+                } else {
+                    IndexedImage::from_color_image(processed_image)
+                };
+                indexed_image.and_then(|indexed_image| indexed_image.save(&path))
+            } else {
+                image_loader::save_image_to_path(&path, processed_image)
+            };
+            if let Err(e) = result {
+                self.error_message = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    fn show_error_window(&mut self, ctx: &egui::Context) {
+        let Some(message) = &self.error_message else {
+            return;
+        };
+        let mut dismissed = false;
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(message);
+                dismissed = ui.button("OK").clicked();
+            });
+        if dismissed {
+            self.error_message = None;
+        }
+    }
+
     fn file_dialog_change_image(&mut self) {
         if let Some(path) = FileDialog::new()
             .add_filter("Image", &["png", "jpg", "jpeg"])
@@ -187,11 +1088,48 @@ impl App {
             AlgorithmType::Dithering => {
                 AlgorithmParameters::Dithering(self.current_dithering_parameters)
             }
+            AlgorithmType::ErrorDiffusion => {
+                AlgorithmParameters::ErrorDiffusion(self.current_error_diffusion_parameters.clone())
+            }
+            AlgorithmType::Riemersma => {
+                AlgorithmParameters::Riemersma(self.current_riemersma_parameters.clone())
+            }
+            AlgorithmType::OrderedDithering => AlgorithmParameters::OrderedDithering(
+                self.current_ordered_dithering_parameters.clone(),
+            ),
+            AlgorithmType::RandomOrderedDithering => AlgorithmParameters::RandomOrderedDithering(
+                self.current_random_ordered_dithering_parameters.clone(),
+            ),
+            AlgorithmType::BlueNoiseDithering => AlgorithmParameters::BlueNoiseDithering(
+                self.current_blue_noise_dithering_parameters.clone(),
+            ),
+            AlgorithmType::Halftone => {
+                AlgorithmParameters::Halftone(self.current_halftone_parameters.clone())
+            }
+            AlgorithmType::PatternDithering => AlgorithmParameters::PatternDithering(
+                self.current_pattern_dithering_parameters.clone(),
+            ),
             AlgorithmType::Popularity => {
                 AlgorithmParameters::Popularity(self.current_popularity_algorithm_parameters)
             }
+            AlgorithmType::MedianCut => {
+                AlgorithmParameters::MedianCut(self.current_median_cut_parameters)
+            }
+            AlgorithmType::Octree => AlgorithmParameters::Octree(self.current_octree_parameters),
+            AlgorithmType::KMeans => AlgorithmParameters::KMeans(self.current_k_means_parameters),
+            AlgorithmType::Wu => AlgorithmParameters::Wu(self.current_wu_parameters),
+            AlgorithmType::NeuQuant => {
+                AlgorithmParameters::NeuQuant(self.current_neu_quant_parameters)
+            }
+            AlgorithmType::FixedPalette => {
+                AlgorithmParameters::FixedPalette(self.current_fixed_palette_parameters.clone())
+            }
         };
-        AlgorithmCacheKey { algorithm, params }
+        AlgorithmCacheKey {
+            algorithm,
+            params,
+            alpha: self.current_alpha_parameters,
+        }
     }
 
     fn update_image(&mut self) {
@@ -201,7 +1139,8 @@ impl App {
                 let processed_image = self
                     .processed_images_cache
                     .get(alg_cache_key, initial_image);
-                self.processed_image = Some(processed_image.to_owned());
+                self.metrics = processed_image.metrics;
+                self.processed_image = Some(processed_image.image.to_owned());
             }
             self.need_image_update = false;
         }
@@ -215,12 +1154,46 @@ impl Default for App {
             algorithm: Algorithm::AverageDithering,
             last_processed_dithering_parameters: DitheringParameters::default(),
             current_dithering_parameters: DitheringParameters::default(),
+            last_processed_error_diffusion_parameters: ErrorDiffusionParameters::default(),
+            current_error_diffusion_parameters: ErrorDiffusionParameters::default(),
+            last_processed_riemersma_parameters: RiemersmaParameters::default(),
+            current_riemersma_parameters: RiemersmaParameters::default(),
+            last_processed_ordered_dithering_parameters: OrderedDitheringParameters::default(),
+            current_ordered_dithering_parameters: OrderedDitheringParameters::default(),
+            last_processed_random_ordered_dithering_parameters:
+                RandomOrderedDitheringParameters::default(),
+            current_random_ordered_dithering_parameters: RandomOrderedDitheringParameters::default(
+            ),
+            last_processed_blue_noise_dithering_parameters: BlueNoiseDitheringParameters::default(),
+            current_blue_noise_dithering_parameters: BlueNoiseDitheringParameters::default(),
+            last_processed_halftone_parameters: HalftoneParameters::default(),
+            current_halftone_parameters: HalftoneParameters::default(),
+            last_processed_pattern_dithering_parameters: PatternDitheringParameters::default(),
+            current_pattern_dithering_parameters: PatternDitheringParameters::default(),
             last_processed_popularity_algorithm_parameters: PopularityParameters::default(),
             current_popularity_algorithm_parameters: PopularityParameters::default(),
+            last_processed_median_cut_parameters: MedianCutParameters::default(),
+            current_median_cut_parameters: MedianCutParameters::default(),
+            last_processed_octree_parameters: OctreeParameters::default(),
+            current_octree_parameters: OctreeParameters::default(),
+            last_processed_k_means_parameters: KMeansParameters::default(),
+            current_k_means_parameters: KMeansParameters::default(),
+            last_processed_wu_parameters: WuParameters::default(),
+            current_wu_parameters: WuParameters::default(),
+            last_processed_neu_quant_parameters: NeuQuantParameters::default(),
+            current_neu_quant_parameters: NeuQuantParameters::default(),
+            last_processed_fixed_palette_parameters: FixedPaletteMappingParameters::default(),
+            current_fixed_palette_parameters: FixedPaletteMappingParameters::default(),
+            last_processed_alpha_parameters: AlphaParameters::default(),
+            current_alpha_parameters: AlphaParameters::default(),
             initial_image: None,
             processed_image: None,
+            metrics: None,
             processed_images_cache: ProcessedImagesCache::new(NonZero::new(CACHE_SIZE).unwrap()),
             need_image_update: true,
+            save_indexed: false,
+            requantize_indexed: false,
+            error_message: None,
         }
     }
 }
@@ -230,5 +1203,6 @@ impl eframe::App for App {
         self.update_image();
         self.show_controls_panel(ctx);
         self.show_central_panel(ctx);
+        self.show_error_window(ctx);
     }
 }
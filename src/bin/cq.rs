@@ -0,0 +1,795 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{builder::RangedU64ValueParser, Parser};
+use color_quantizer::{
+    algorithms::{
+        Algorithm, AlgorithmCacheKey, AlgorithmParameters, AlgorithmType, AlphaMode,
+        AlphaParameters, BayerMatrixSize, BlueNoiseDitheringParameters, BlueNoiseSize,
+        BuiltinPalette, DistanceMetric, DitheringParameters, DitheringTarget, ErrorDiffusionKernel,
+        ErrorDiffusionParameters, FixedPalette, FixedPaletteDithering,
+        FixedPaletteMappingParameters, FixedPaletteParameters, HalftoneParameters,
+        HalftoneSpotFunction, KMeansInitialization, KMeansParameters, MedianCutParameters,
+        NeuQuantParameters, OctreeParameters, OrderedDitheringParameters, PaletteSource,
+        PatternDitheringMethod, PatternDitheringParameters, PatternSize, PopularityParameters,
+        RandomOrderedDitheringParameters, RiemersmaParameters, WuParameters,
+    },
+    image_loader::{load_image_from_path, save_image_to_path},
+    indexed_image::IndexedImage,
+    palette::Palette,
+    processed_images_cache::ProcessedImagesCache,
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use strum::IntoEnumIterator;
+
+// Some of the images (or the palette file) couldn't be read, processed or written
+const PROCESSING_ERROR: u8 = 1;
+// Invalid arguments, the same code is used by clap
+const USAGE_ERROR: u8 = 2;
+
+// Placeholder in the output path replaced with the input file name without extension
+const NAME_PLACEHOLDER: &str = "{name}";
+
+/// Quantizes colors of images without a display, all images are processed in parallel
+#[derive(Parser)]
+#[command(name = "cq", version)]
+struct Args {
+    /// Input images, glob patterns (e.g. "images/*.png") are expanded
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Output path, "{name}" is replaced with the input file name without extension.
+    /// If it is an existing directory, input file names are kept.
+    /// Format is deduced from the extension
+    #[arg(short, long)]
+    output: String,
+
+    /// Save paletted PNG or GIF, fails when the output has more than 256 colors
+    #[arg(long)]
+    indexed: bool,
+
+    /// Requantize indexed output with more than 256 colors using median cut instead of failing
+    #[arg(long, requires = "indexed")]
+    requantize: bool,
+
+    /// Algorithm, e.g. "median-cut" or "Error Diffusion Dithering"
+    #[arg(short, long, value_parser = parse_name::<Algorithm>)]
+    algorithm: Algorithm,
+
+    /// Number of red levels used by dithering
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(2..))]
+    kr: u8,
+
+    /// Number of green levels used by dithering
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(2..))]
+    kg: u8,
+
+    /// Number of blue levels used by dithering
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(2..))]
+    kb: u8,
+
+    /// Match averages of dithering levels in linear light
+    #[arg(long)]
+    linear_light: bool,
+
+    /// Palette algorithm used as the target of dithering, parameters are shared with the palette algorithms
+    #[arg(long, value_parser = parse_name::<Algorithm>)]
+    palette: Option<Algorithm>,
+
+    /// Palette file (GPL, ACT, JASC-PAL, Paint.NET or hex list) or an image whose colors form the palette, used by the fixed palette algorithm
+    #[arg(long, conflicts_with = "builtin_palette")]
+    palette_file: Option<PathBuf>,
+
+    /// Built-in palette used by the fixed palette algorithm (e.g. pico-8 or "Game Boy (DMG)")
+    #[arg(long, value_parser = parse_name::<BuiltinPalette>)]
+    builtin_palette: Option<BuiltinPalette>,
+
+    /// Dithering used when mapping to the fixed palette, configured with --kernel, --serpentine, --error-clamp and --matrix-size
+    #[arg(long, value_parser = parse_name::<FixedPaletteDithering>)]
+    palette_dithering: Option<FixedPaletteDithering>,
+
+    /// Number of colors of palette algorithms
+    #[arg(short, long, default_value_t = 16, value_parser = RangedU64ValueParser::<usize>::new().range(2..=8192))]
+    k: usize,
+
+    /// Metric used for finding the closest palette color
+    #[arg(long, value_parser = parse_name::<DistanceMetric>)]
+    metric: Option<DistanceMetric>,
+
+    /// Seed of random ordered dithering and K-Means
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Error diffusion kernel
+    #[arg(long, value_parser = parse_name::<ErrorDiffusionKernel>)]
+    kernel: Option<ErrorDiffusionKernel>,
+
+    /// Scan every other row right to left in error diffusion
+    #[arg(long)]
+    serpentine: bool,
+
+    /// Maximum absolute error accumulated in each channel by error diffusion
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    error_clamp: Option<u8>,
+
+    /// Length of the error history of Riemersma dithering
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=64))]
+    queue_length: Option<u8>,
+
+    /// Ratio between weights of the oldest and the newest error of Riemersma dithering
+    #[arg(long)]
+    ratio: Option<f32>,
+
+    /// Size of the Bayer matrix, e.g. "auto" or "8x8"
+    #[arg(long, value_parser = parse_name::<BayerMatrixSize>)]
+    matrix_size: Option<BayerMatrixSize>,
+
+    /// Size of the blue noise map, e.g. "64x64"
+    #[arg(long, value_parser = parse_name::<BlueNoiseSize>)]
+    noise_size: Option<BlueNoiseSize>,
+
+    /// Shape of halftone dots
+    #[arg(long, value_parser = parse_name::<HalftoneSpotFunction>)]
+    spot_function: Option<HalftoneSpotFunction>,
+
+    /// Halftone screen frequency in cells per inch
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    frequency: Option<u16>,
+
+    /// Resolution of the image in pixels per inch used by halftone
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    dpi: Option<u16>,
+
+    /// Halftone screen angles in degrees for red, green, blue and black (K) channels, e.g. "15,75,0,45"
+    #[arg(long, value_delimiter = ',')]
+    angles: Option<Vec<u16>>,
+
+    /// Pattern dithering method
+    #[arg(long, value_parser = parse_name::<PatternDitheringMethod>)]
+    method: Option<PatternDitheringMethod>,
+
+    /// Size of the pattern dithering threshold matrix, e.g. "4x4"
+    #[arg(long, value_parser = parse_name::<PatternSize>)]
+    pattern_size: Option<PatternSize>,
+
+    /// Maximum depth of the octree
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=8))]
+    max_depth: u8,
+
+    /// Maximum number of K-Means iterations
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    max_iterations: u32,
+
+    /// K-Means stops when no palette color moved further than this distance
+    #[arg(long, default_value_t = 0.5)]
+    convergence_threshold: f32,
+
+    /// How the starting palette of K-Means is chosen
+    #[arg(long, value_parser = parse_name::<KMeansInitialization>)]
+    initialization: Option<KMeansInitialization>,
+
+    /// Only every n-th pixel is used for learning by NeuQuant
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=30))]
+    sampling_factor: u8,
+
+    /// How transparency is handled
+    #[arg(long, value_parser = parse_name::<AlphaMode>)]
+    alpha: Option<AlphaMode>,
+
+    /// Pixels with alpha below the threshold become fully transparent
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    alpha_threshold: Option<u8>,
+
+    /// Number of alpha levels used by alpha dithering
+    #[arg(long, value_parser = clap::value_parser!(u8).range(2..))]
+    ka: Option<u8>,
+}
+
+// Failure which happened before processing, together with the exit code it ends with
+struct PrepareError {
+    code: u8,
+    error: anyhow::Error,
+}
+
+impl PrepareError {
+    fn usage(error: anyhow::Error) -> PrepareError {
+        PrepareError {
+            code: USAGE_ERROR,
+            error,
+        }
+    }
+
+    // Files given in arguments couldn't be read
+    fn read(error: anyhow::Error) -> PrepareError {
+        PrepareError {
+            code: PROCESSING_ERROR,
+            error,
+        }
+    }
+}
+
+// Names are compared ignoring case, spaces and punctuation, both displayed and variant names are accepted
+fn parse_name<T: IntoEnumIterator + Display + Debug>(name: &str) -> Result<T, String> {
+    let normalize = |name: &str| {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    let name = normalize(name);
+    T::iter()
+        .find(|value| {
+            // Variants with fields are matched only by their name
+            let debug = format!("{:?}", value);
+            let variant = debug.split([' ', '(', '{']).next().unwrap_or_default();
+            normalize(&value.to_string()) == name || normalize(variant) == name
+        })
+        .ok_or_else(|| {
+            let possible: Vec<_> = T::iter().map(|value| format!("\"{}\"", value)).collect();
+            format!("possible values: {}", possible.join(", "))
+        })
+}
+
+fn dithering_parameters(args: &Args) -> DitheringParameters {
+    DitheringParameters {
+        k_r: args.kr,
+        k_g: args.kg,
+        k_b: args.kb,
+        linear_light: args.linear_light,
+    }
+}
+
+// Only palette algorithms are accepted, so that building the palette never needs another palette
+// `imported_palette` is the palette read from `--palette-file`
+fn palette_source(
+    args: &Args,
+    imported_palette: Option<&FixedPalette>,
+    algorithm: Algorithm,
+) -> Result<PaletteSource> {
+    let metric = args.metric.unwrap_or_default();
+    let source = match AlgorithmType::from(algorithm) {
+        AlgorithmType::Popularity => {
+            PaletteSource::Popularity(PopularityParameters { k: args.k, metric })
+        }
+        AlgorithmType::MedianCut => {
+            PaletteSource::MedianCut(MedianCutParameters { k: args.k, metric })
+        }
+        AlgorithmType::Octree => PaletteSource::Octree(OctreeParameters {
+            k: args.k,
+            max_depth: args.max_depth,
+            metric,
+        }),
+        AlgorithmType::KMeans => PaletteSource::KMeans(KMeansParameters {
+            k: limited_k(args, algorithm)?,
+            max_iterations: args.max_iterations,
+            convergence_threshold: args.convergence_threshold,
+            seed: args.seed.unwrap_or_default(),
+            initialization: args.initialization.unwrap_or_default(),
+            metric,
+        }),
+        AlgorithmType::Wu => PaletteSource::Wu(WuParameters {
+            k: limited_k(args, algorithm)?,
+            metric,
+        }),
+        AlgorithmType::NeuQuant => PaletteSource::NeuQuant(NeuQuantParameters {
+            k: limited_k(args, algorithm)?,
+            sampling_factor: args.sampling_factor,
+            metric,
+        }),
+        AlgorithmType::FixedPalette => {
+            let palette = match (imported_palette.cloned(), args.builtin_palette) {
+                (Some(palette), _) => palette,
+                (None, Some(palette)) => FixedPalette::Builtin(palette),
+                (None, None) => bail!("{} requires --palette-file or --builtin-palette", algorithm),
+            };
+            PaletteSource::Fixed(FixedPaletteParameters { palette, metric })
+        }
+        _ => bail!("{} doesn't generate a palette", algorithm),
+    };
+    Ok(source)
+}
+
+fn dithering_target(
+    args: &Args,
+    imported_palette: Option<&FixedPalette>,
+) -> Result<DitheringTarget> {
+    match args.palette {
+        Some(algorithm) => Ok(DitheringTarget::Palette(palette_source(
+            args,
+            imported_palette,
+            algorithm,
+        )?)),
+        None => Ok(DitheringTarget::Levels),
+    }
+}
+
+// Parameters which aren't given in arguments use defaults of their algorithm
+fn algorithm_parameters(
+    args: &Args,
+    imported_palette: Option<&FixedPalette>,
+    algorithm: Algorithm,
+) -> Result<AlgorithmParameters> {
+    let params = match AlgorithmType::from(algorithm) {
+        AlgorithmType::Dithering => AlgorithmParameters::Dithering(dithering_parameters(args)),
+        AlgorithmType::ErrorDiffusion => {
+            AlgorithmParameters::ErrorDiffusion(ErrorDiffusionParameters {
+                dithering: dithering_parameters(args),
+                kernel: args.kernel.unwrap_or_default(),
+                serpentine: args.serpentine,
+                error_clamp: args.error_clamp,
+                target: dithering_target(args, imported_palette)?,
+            })
+        }
+        AlgorithmType::Riemersma => {
+            let defaults = RiemersmaParameters::default();
+            let ratio = args.ratio.unwrap_or(defaults.ratio);
+            if !(ratio > 0.0 && ratio <= 1.0) {
+                bail!("ratio must be in range (0, 1]");
+            }
+            AlgorithmParameters::Riemersma(RiemersmaParameters {
+                dithering: dithering_parameters(args),
+                target: dithering_target(args, imported_palette)?,
+                queue_length: args.queue_length.unwrap_or(defaults.queue_length),
+                ratio,
+            })
+        }
+        AlgorithmType::OrderedDithering => {
+            AlgorithmParameters::OrderedDithering(OrderedDitheringParameters {
+                dithering: dithering_parameters(args),
+                target: dithering_target(args, imported_palette)?,
+                matrix_size: args.matrix_size.unwrap_or_default(),
+            })
+        }
+        AlgorithmType::RandomOrderedDithering => {
+            AlgorithmParameters::RandomOrderedDithering(RandomOrderedDitheringParameters {
+                dithering: dithering_parameters(args),
+                target: dithering_target(args, imported_palette)?,
+                matrix_size: args.matrix_size.unwrap_or_default(),
+                seed: args.seed.unwrap_or_default(),
+            })
+        }
+        AlgorithmType::BlueNoiseDithering => {
+            AlgorithmParameters::BlueNoiseDithering(BlueNoiseDitheringParameters {
+                dithering: dithering_parameters(args),
+                target: dithering_target(args, imported_palette)?,
+                size: args.noise_size.unwrap_or_default(),
+            })
+        }
+        AlgorithmType::Halftone => {
+            let defaults = HalftoneParameters::default();
+            let dpi = args.dpi.unwrap_or(defaults.dpi);
+            let frequency = args.frequency.unwrap_or(defaults.frequency);
+            if frequency > dpi / 2 {
+                bail!("frequency must not be greater than half of dpi");
+            }
+            let angles = match args.angles.as_deref() {
+                Some(&[r, g, b, k]) => [r, g, b, k],
+                Some(_) => bail!("angles must contain exactly 4 values"),
+                None => defaults.angles,
+            };
+            AlgorithmParameters::Halftone(HalftoneParameters {
+                dithering: dithering_parameters(args),
+                target: dithering_target(args, imported_palette)?,
+                spot_function: args.spot_function.unwrap_or_default(),
+                frequency,
+                dpi,
+                angles: angles.map(|angle| angle % 180),
+            })
+        }
+        AlgorithmType::PatternDithering => {
+            let defaults = PatternDitheringParameters::default();
+            let palette = match args.palette {
+                Some(algorithm) => palette_source(args, imported_palette, algorithm)?,
+                None => defaults.palette,
+            };
+            AlgorithmParameters::PatternDithering(PatternDitheringParameters {
+                method: args.method.unwrap_or_default(),
+                palette,
+                size: args.pattern_size.unwrap_or_default(),
+            })
+        }
+        AlgorithmType::Popularity
+        | AlgorithmType::MedianCut
+        | AlgorithmType::Octree
+        | AlgorithmType::KMeans
+        | AlgorithmType::Wu
+        | AlgorithmType::NeuQuant
+        | AlgorithmType::FixedPalette => match palette_source(args, imported_palette, algorithm)? {
+            PaletteSource::Popularity(params) => AlgorithmParameters::Popularity(params),
+            PaletteSource::MedianCut(params) => AlgorithmParameters::MedianCut(params),
+            PaletteSource::Octree(params) => AlgorithmParameters::Octree(params),
+            PaletteSource::KMeans(params) => AlgorithmParameters::KMeans(params),
+            PaletteSource::Wu(params) => AlgorithmParameters::Wu(params),
+            PaletteSource::NeuQuant(params) => AlgorithmParameters::NeuQuant(params),
+            PaletteSource::Fixed(palette) => {
+                AlgorithmParameters::FixedPalette(FixedPaletteMappingParameters {
+                    palette,
+                    dithering: palette_dithering(args),
+                })
+            }
+        },
+    };
+    Ok(params)
+}
+
+fn palette_dithering(args: &Args) -> FixedPaletteDithering {
+    match args.palette_dithering.unwrap_or_default() {
+        FixedPaletteDithering::None => FixedPaletteDithering::None,
+        FixedPaletteDithering::ErrorDiffusion { .. } => FixedPaletteDithering::ErrorDiffusion {
+            kernel: args.kernel.unwrap_or_default(),
+            serpentine: args.serpentine,
+            error_clamp: args.error_clamp,
+        },
+        FixedPaletteDithering::Ordered { .. } => FixedPaletteDithering::Ordered {
+            matrix_size: args.matrix_size.unwrap_or_default(),
+        },
+    }
+}
+
+// Some algorithms generate at most 256 colors
+fn limited_k(args: &Args, algorithm: Algorithm) -> Result<usize> {
+    if args.k > 256 {
+        bail!("{} supports at most 256 colors", algorithm);
+    }
+    Ok(args.k)
+}
+
+fn alpha_parameters(args: &Args) -> AlphaParameters {
+    let defaults = AlphaParameters::default();
+    AlphaParameters {
+        mode: args.alpha.unwrap_or_default(),
+        threshold: args.alpha_threshold.unwrap_or(defaults.threshold),
+        k_a: args.ka.unwrap_or(defaults.k_a),
+    }
+}
+
+// Inputs which aren't existing files are treated as glob patterns
+fn input_paths(inputs: &[String]) -> Result<Vec<PathBuf>, PrepareError> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        if Path::new(input).is_file() {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+        let matches = glob::glob(input)
+            .with_context(|| format!("invalid pattern {}", input))
+            .map_err(PrepareError::usage)?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("failed to read files matching {}", input))
+            .map_err(PrepareError::read)?;
+        let matches: Vec<_> = matches.into_iter().filter(|path| path.is_file()).collect();
+        if matches.is_empty() {
+            return Err(PrepareError::read(anyhow!("no files match {}", input)));
+        }
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+fn output_path(output: &str, input: &Path) -> PathBuf {
+    let name = input.file_stem().unwrap_or_default().to_string_lossy();
+    let output = PathBuf::from(output.replace(NAME_PLACEHOLDER, &name));
+    if output.is_dir() {
+        output.join(input.file_name().unwrap_or_default())
+    } else {
+        output
+    }
+}
+
+fn process(args: &Args, key: &AlgorithmCacheKey, input: &Path, output: &Path) -> Result<()> {
+    let image = load_image_from_path(input).context("failed to read image")?;
+    let processed_image = ProcessedImagesCache::create_new_image(key, &image);
+    if !args.indexed {
+        return save_image_to_path(output, &processed_image);
+    }
+    let indexed_image = if args.requantize {
+        IndexedImage::from_color_image_requantized(&processed_image)?
+    } else {
+        IndexedImage::from_color_image(&processed_image)?
+    };
+    indexed_image.save(output)
+}
+
+// Reads the palette given with `--palette-file`, if there is any
+fn imported_palette(args: &Args) -> Result<Option<FixedPalette>, PrepareError> {
+    match &args.palette_file {
+        Some(path) => {
+            let palette = Palette::load(path).map_err(PrepareError::read)?;
+            Ok(Some(palette.into_fixed()))
+        }
+        None => Ok(None),
+    }
+}
+
+// Validates arguments, reads the palette file and pairs inputs with outputs
+fn prepare(args: &Args) -> Result<(AlgorithmCacheKey, Vec<(PathBuf, PathBuf)>), PrepareError> {
+    let accepts_palette = matches!(
+        AlgorithmType::from(args.algorithm),
+        AlgorithmType::ErrorDiffusion
+            | AlgorithmType::Riemersma
+            | AlgorithmType::OrderedDithering
+            | AlgorithmType::RandomOrderedDithering
+            | AlgorithmType::BlueNoiseDithering
+            | AlgorithmType::Halftone
+            | AlgorithmType::PatternDithering
+    );
+    if args.palette.is_some() && !accepts_palette {
+        return Err(PrepareError::usage(anyhow!(
+            "{} doesn't accept a palette",
+            args.algorithm
+        )));
+    }
+    // Fixed palette options would be silently ignored by other algorithms
+    let is_fixed_palette = args.algorithm == Algorithm::FixedPalette;
+    let uses_fixed_palette = is_fixed_palette || args.palette == Some(Algorithm::FixedPalette);
+    if (args.palette_file.is_some() || args.builtin_palette.is_some()) && !uses_fixed_palette {
+        return Err(PrepareError::usage(anyhow!(
+            "--palette-file and --builtin-palette require {} as the algorithm or --palette",
+            Algorithm::FixedPalette
+        )));
+    }
+    // Dithering targets don't map to their palette with another dithering
+    if args.palette_dithering.is_some() && !is_fixed_palette {
+        return Err(PrepareError::usage(anyhow!(
+            "--palette-dithering requires {} as the algorithm",
+            Algorithm::FixedPalette
+        )));
+    }
+    let imported_palette = imported_palette(args)?;
+    let key = AlgorithmCacheKey {
+        algorithm: args.algorithm,
+        params: algorithm_parameters(args, imported_palette.as_ref(), args.algorithm)
+            .map_err(PrepareError::usage)?,
+        alpha: alpha_parameters(args),
+    };
+    let is_pattern = args.output.contains(NAME_PLACEHOLDER) || Path::new(&args.output).is_dir();
+    if args.inputs.len() > 1 && !is_pattern {
+        return Err(PrepareError::usage(anyhow!(
+            "output must be a directory or contain {} when there are multiple inputs",
+            NAME_PLACEHOLDER
+        )));
+    }
+    let inputs = input_paths(&args.inputs)?;
+    if inputs.len() > 1 && !is_pattern {
+        return Err(PrepareError::usage(anyhow!(
+            "output must be a directory or contain {} when patterns match multiple files",
+            NAME_PLACEHOLDER
+        )));
+    }
+    let jobs: Vec<(PathBuf, PathBuf)> = inputs
+        .into_iter()
+        .map(|input| {
+            let output = output_path(&args.output, &input);
+            (input, output)
+        })
+        .collect();
+    // Inputs with the same file name from different directories would overwrite each other's output
+    let mut outputs = HashMap::new();
+    for (input, output) in &jobs {
+        if let Some(other) = outputs.insert(output, input) {
+            return Err(PrepareError::usage(anyhow!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            )));
+        }
+        if args.indexed && !IndexedImage::is_supported_path(output) {
+            return Err(PrepareError::usage(anyhow!(
+                "indexed colors can be saved only as PNG or GIF, not {}",
+                output.display()
+            )));
+        }
+    }
+    Ok((key, jobs))
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let (key, jobs) = match prepare(&args) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            eprintln!("error: {:#}", e.error);
+            return ExitCode::from(e.code);
+        }
+    };
+    let failures = jobs
+        .par_iter()
+        .filter(
+            |(input, output)| match process(&args, &key, input, output) {
+                Ok(()) => {
+                    println!("{} -> {}", input.display(), output.display());
+                    false
+                }
+                Err(e) => {
+                    eprintln!("error: {}: {:#}", input.display(), e);
+                    true
+                }
+            },
+        )
+        .count();
+    if failures > 0 {
+        eprintln!("{} of {} images failed", failures, jobs.len());
+        return ExitCode::from(PROCESSING_ERROR);
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Directory with empty input files, removed even when the test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str, files: &[&str]) -> TestDir {
+            let dir = std::env::temp_dir().join(format!("cq-{}-{}", name, std::process::id()));
+            for file in files {
+                let path = dir.join(file);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, []).unwrap();
+            }
+            TestDir(dir)
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.0.join(file).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn prepare_args(
+        args: &[&str],
+    ) -> Result<(AlgorithmCacheKey, Vec<(PathBuf, PathBuf)>), PrepareError> {
+        let args = Args::try_parse_from(["cq"].iter().chain(args)).unwrap();
+        prepare(&args)
+    }
+
+    #[test]
+    fn names_are_parsed_ignoring_case_and_punctuation() {
+        for name in ["median-cut", "Median Cut", "MEDIAN_CUT", "mediancut"] {
+            assert_eq!(parse_name::<Algorithm>(name), Ok(Algorithm::MedianCut));
+        }
+        assert_eq!(parse_name::<Algorithm>("Wu's Algorithm"), Ok(Algorithm::Wu));
+        assert_eq!(
+            parse_name::<BuiltinPalette>("Game Boy (DMG)"),
+            parse_name::<BuiltinPalette>("game-boy-dmg")
+        );
+        // Variants with fields are matched by their name
+        assert!(matches!(
+            parse_name::<FixedPaletteDithering>("ordered"),
+            Ok(FixedPaletteDithering::Ordered { .. })
+        ));
+        let error = parse_name::<Algorithm>("median").unwrap_err();
+        assert!(error.contains("\"Median Cut\""), "{}", error);
+    }
+
+    #[test]
+    fn output_name_placeholder_is_replaced() {
+        assert_eq!(
+            output_path("out/{name}-cq.png", Path::new("images/photo.jpg")),
+            PathBuf::from("out/photo-cq.png")
+        );
+        assert_eq!(
+            output_path("out.png", Path::new("images/photo.jpg")),
+            PathBuf::from("out.png")
+        );
+    }
+
+    #[test]
+    fn output_directory_keeps_input_file_name() {
+        let dir = TestDir::new("output-directory", &["out/.keep"]);
+        assert_eq!(
+            output_path(&dir.path("out"), Path::new("images/photo.jpg")),
+            PathBuf::from(dir.path("out/photo.jpg"))
+        );
+    }
+
+    #[test]
+    fn inputs_with_the_same_output_are_rejected() {
+        let dir = TestDir::new("duplicate-outputs", &["a/photo.png", "b/photo.png"]);
+        let result = prepare_args(&[
+            &dir.path("a/photo.png"),
+            &dir.path("b/photo.png"),
+            "-a",
+            "median-cut",
+            "-o",
+            &dir.path("{name}.png"),
+        ]);
+        let error = result.err().unwrap();
+        assert_eq!(error.code, USAGE_ERROR);
+        assert!(
+            error.error.to_string().contains("would both be written"),
+            "{}",
+            error.error
+        );
+    }
+
+    #[test]
+    fn indexed_output_requires_png_or_gif() {
+        let dir = TestDir::new("indexed-extension", &["photo.png"]);
+        let prepare_with_output = |output: &str| {
+            prepare_args(&[
+                &dir.path("photo.png"),
+                "-a",
+                "median-cut",
+                "--indexed",
+                "-o",
+                &dir.path(output),
+            ])
+        };
+        assert_eq!(
+            prepare_with_output("out.jpg").err().unwrap().code,
+            USAGE_ERROR
+        );
+        assert!(prepare_with_output("out.png").is_ok());
+        assert!(prepare_with_output("out.gif").is_ok());
+    }
+
+    #[test]
+    fn fixed_palette_options_require_fixed_palette() {
+        let dir = TestDir::new("fixed-palette-options", &["photo.png"]);
+        let prepare_with = |options: &[&str]| {
+            let input = dir.path("photo.png");
+            let output = dir.path("out.png");
+            let mut args = vec![input.as_str(), "-o", output.as_str()];
+            args.extend(options);
+            prepare_args(&args)
+        };
+        for options in [
+            &["-a", "median-cut", "--builtin-palette", "nes"][..],
+            &["-a", "median-cut", "--palette-dithering", "ordered"],
+            &[
+                "-a",
+                "error-diffusion-dithering",
+                "--builtin-palette",
+                "nes",
+            ],
+            &[
+                "-a",
+                "error-diffusion-dithering",
+                "--palette",
+                "fixed-palette",
+                "--builtin-palette",
+                "nes",
+                "--palette-dithering",
+                "ordered",
+            ],
+        ] {
+            let error = prepare_with(options).err().unwrap();
+            assert_eq!(error.code, USAGE_ERROR, "{:?}", options);
+        }
+        for options in [
+            &["-a", "fixed-palette", "--builtin-palette", "nes"][..],
+            &[
+                "-a",
+                "fixed-palette",
+                "--builtin-palette",
+                "nes",
+                "--palette-dithering",
+                "ordered",
+            ],
+            &[
+                "-a",
+                "error-diffusion-dithering",
+                "--palette",
+                "fixed-palette",
+                "--builtin-palette",
+                "nes",
+            ],
+        ] {
+            assert!(prepare_with(options).is_ok(), "{:?}", options);
+        }
+    }
+}
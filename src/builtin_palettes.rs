@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use egui::Color32;
+
+use crate::algorithms::BuiltinPalette;
+
+// Colors of IBM CGA in the order of RGBI indices, 16 color modes of EGA and VGA use them too
+const CGA: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA, 0x555555,
+    0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+// Indices of CGA colors available in the 4 color modes, background is black
+const CGA_MODE_4_PALETTE_0_LOW: [usize; 4] = [0, 2, 4, 6];
+const CGA_MODE_4_PALETTE_0_HIGH: [usize; 4] = [0, 10, 12, 14];
+const CGA_MODE_4_PALETTE_1_LOW: [usize; 4] = [0, 3, 5, 7];
+const CGA_MODE_4_PALETTE_1_HIGH: [usize; 4] = [0, 11, 13, 15];
+const CGA_MODE_5_LOW: [usize; 4] = [0, 3, 4, 7];
+const CGA_MODE_5_HIGH: [usize; 4] = [0, 11, 12, 15];
+
+// Philip "Pepto" Timmermann's measurements of the VIC-II
+const COMMODORE_64: [u32; 16] = [
+    0x000000, 0xFFFFFF, 0x68372B, 0x70A4B2, 0x6F3D86, 0x588D43, 0x352879, 0xB8C76F, 0x6F4F25,
+    0x433900, 0x9A6759, 0x444444, 0x6C6C6C, 0x9AD284, 0x6C5EB5, 0x959595,
+];
+
+const ZX_SPECTRUM_NORMAL: [u32; 8] = [
+    0x000000, 0x0000D8, 0xD80000, 0xD800D8, 0x00D800, 0x00D8D8, 0xD8D800, 0xD8D8D8,
+];
+
+const ZX_SPECTRUM_BRIGHT: [u32; 8] = [
+    0x000000, 0x0000FF, 0xFF0000, 0xFF00FF, 0x00FF00, 0x00FFFF, 0xFFFF00, 0xFFFFFF,
+];
+
+// All 64 entries of the 2C02 PPU, unused entries are black
+const NES: [u32; 64] = [
+    0x7C7C7C, 0x0000FC, 0x0000BC, 0x4428BC, 0x940084, 0xA80020, 0xA81000, 0x881400, 0x503000,
+    0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0x000000, 0x000000, 0xBCBCBC, 0x0078F8,
+    0x0058F8, 0x6844FC, 0xD800CC, 0xE40058, 0xF83800, 0xE45C10, 0xAC7C00, 0x00B800, 0x00A800,
+    0x00A844, 0x008888, 0x000000, 0x000000, 0x000000, 0xF8F8F8, 0x3CBCFC, 0x6888FC, 0x9878F8,
+    0xF878F8, 0xF85898, 0xF87858, 0xFCA044, 0xF8B800, 0xB8F818, 0x58D854, 0x58F898, 0x00E8D8,
+    0x787878, 0x000000, 0x000000, 0xFCFCFC, 0xA4E4FC, 0xB8B8F8, 0xD8B8F8, 0xF8B8F8, 0xF8A4C0,
+    0xF0D0B0, 0xFCE0A8, 0xF8D878, 0xD8F878, 0xB8F8B8, 0xB8F8D8, 0x00FCFC, 0xF8D8F8, 0x000000,
+    0x000000,
+];
+
+// Shades of green of the original Game Boy screen, from the darkest
+const GAME_BOY: [u32; 4] = [0x0F380F, 0x306230, 0x8BAC0F, 0x9BBC0F];
+
+const PICO_8: [u32; 16] = [
+    0x000000, 0x1D2B53, 0x7E2553, 0x008751, 0xAB5236, 0x5F574F, 0xC2C3C7, 0xFFF1E8, 0xFF004D,
+    0xFFA300, 0xFFEC27, 0x00E436, 0x29ADFF, 0x83769C, 0xFF77A8, 0xFFCCAA,
+];
+
+// Colors of the TMS9918 video chip, without the transparent one
+const MSX: [u32; 15] = [
+    0x000000, 0x3EB849, 0x74D07D, 0x5955E0, 0x8076F1, 0xB95E51, 0x65DBEF, 0xDB6559, 0xFF897D,
+    0xCCC35E, 0xDED087, 0x3AA241, 0xB766B5, 0xCCCCCC, 0xFFFFFF,
+];
+
+// Low resolution colors, both greys look the same on NTSC displays
+const APPLE_II: [u32; 16] = [
+    0x000000, 0xE31E60, 0x604EBD, 0xFF44FD, 0x00A360, 0x9C9C9C, 0x14CFFD, 0xD0C3FF, 0x607203,
+    0xFF6A3C, 0x9C9C9C, 0xFFA0D0, 0x14F53C, 0xD0DD8D, 0x72FFD0, 0xFFFFFF,
+];
+
+const WINDOWS_16: [u32; 16] = [
+    0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xC0C0C0, 0x808080,
+    0xFF0000, 0x00FF00, 0xFFFF00, 0x0000FF, 0xFF00FF, 0x00FFFF, 0xFFFFFF,
+];
+
+// Default palette of VGA mode 13h uses 6-bit DAC values, after CGA colors there are 16 greys
+const VGA_GREYS: [u8; 16] = [0, 5, 8, 11, 14, 17, 20, 24, 28, 32, 36, 40, 45, 50, 56, 63];
+
+// Greys are followed by 24 hues for each of 3 intensities and 3 saturations, each built from 5 levels
+const VGA_HUE_LEVELS: [[u8; 5]; 9] = [
+    [0, 16, 31, 47, 63],
+    [31, 39, 47, 55, 63],
+    [45, 49, 54, 58, 63],
+    [0, 7, 14, 21, 28],
+    [14, 17, 21, 24, 28],
+    [20, 22, 24, 26, 28],
+    [0, 4, 8, 12, 16],
+    [8, 10, 12, 14, 16],
+    [11, 12, 13, 15, 16],
+];
+
+// Levels of red, green and blue of each hue, going from blue through red and green back to blue
+const VGA_HUES: [[usize; 3]; 24] = [
+    [0, 0, 4],
+    [1, 0, 4],
+    [2, 0, 4],
+    [3, 0, 4],
+    [4, 0, 4],
+    [4, 0, 3],
+    [4, 0, 2],
+    [4, 0, 1],
+    [4, 0, 0],
+    [4, 1, 0],
+    [4, 2, 0],
+    [4, 3, 0],
+    [4, 4, 0],
+    [3, 4, 0],
+    [2, 4, 0],
+    [1, 4, 0],
+    [0, 4, 0],
+    [0, 4, 1],
+    [0, 4, 2],
+    [0, 4, 3],
+    [0, 4, 4],
+    [0, 3, 4],
+    [0, 2, 4],
+    [0, 1, 4],
+];
+
+// Colors of the palette, without duplicates
+pub fn colors(palette: BuiltinPalette) -> Vec<Color32> {
+    let colors = match palette {
+        BuiltinPalette::WebSafe => grid(&[0x00, 0x33, 0x66, 0x99, 0xCC, 0xFF]),
+        BuiltinPalette::Cga => from_hex(&CGA),
+        BuiltinPalette::CgaMode4Palette0Low => cga_mode(CGA_MODE_4_PALETTE_0_LOW),
+        BuiltinPalette::CgaMode4Palette0High => cga_mode(CGA_MODE_4_PALETTE_0_HIGH),
+        BuiltinPalette::CgaMode4Palette1Low => cga_mode(CGA_MODE_4_PALETTE_1_LOW),
+        BuiltinPalette::CgaMode4Palette1High => cga_mode(CGA_MODE_4_PALETTE_1_HIGH),
+        BuiltinPalette::CgaMode5Low => cga_mode(CGA_MODE_5_LOW),
+        BuiltinPalette::CgaMode5High => cga_mode(CGA_MODE_5_HIGH),
+        // Each channel is a sum of a 2/3 and 1/3 intensity bit
+        BuiltinPalette::Ega => grid(&[0x00, 0x55, 0xAA, 0xFF]),
+        BuiltinPalette::Commodore64 => from_hex(&COMMODORE_64),
+        BuiltinPalette::ZxSpectrumNormal => from_hex(&ZX_SPECTRUM_NORMAL),
+        BuiltinPalette::ZxSpectrumBright => from_hex(&ZX_SPECTRUM_BRIGHT),
+        BuiltinPalette::Nes => from_hex(&NES),
+        BuiltinPalette::GameBoy => from_hex(&GAME_BOY),
+        // 5 bits per channel, scaled to 8 bits
+        BuiltinPalette::GameBoyColor => {
+            grid(&(0..32u8).map(|v| (v << 3) | (v >> 2)).collect::<Vec<_>>())
+        }
+        BuiltinPalette::AmstradCpc => grid(&[0x00, 0x80, 0xFF]),
+        BuiltinPalette::Pico8 => from_hex(&PICO_8),
+        BuiltinPalette::Msx => from_hex(&MSX),
+        BuiltinPalette::AppleII => from_hex(&APPLE_II),
+        BuiltinPalette::Windows16 => from_hex(&WINDOWS_16),
+        BuiltinPalette::Vga256 => vga_256(),
+    };
+    let mut seen = HashSet::new();
+    colors
+        .into_iter()
+        .filter(|&color| seen.insert(color))
+        .collect()
+}
+
+fn from_hex(values: &[u32]) -> Vec<Color32> {
+    values
+        .iter()
+        .map(|value| {
+            let [_, r, g, b] = value.to_be_bytes();
+            Color32::from_rgb(r, g, b)
+        })
+        .collect()
+}
+
+// All combinations of the levels in each channel
+fn grid(levels: &[u8]) -> Vec<Color32> {
+    levels
+        .iter()
+        .flat_map(|&r| {
+            levels
+                .iter()
+                .flat_map(move |&g| levels.iter().map(move |&b| Color32::from_rgb(r, g, b)))
+        })
+        .collect()
+}
+
+fn cga_mode(indices: [usize; 4]) -> Vec<Color32> {
+    from_hex(&indices.map(|index| CGA[index]))
+}
+
+fn vga_256() -> Vec<Color32> {
+    let dac = |value: u8| (value << 2) | (value >> 4);
+    let greys = VGA_GREYS.iter().map(|&value| {
+        let grey = dac(value);
+        Color32::from_rgb(grey, grey, grey)
+    });
+    let hues = VGA_HUE_LEVELS.iter().flat_map(|levels| {
+        VGA_HUES.iter().map(|hue| {
+            Color32::from_rgb(
+                dac(levels[hue[0]]),
+                dac(levels[hue[1]]),
+                dac(levels[hue[2]]),
+            )
+        })
+    });
+    from_hex(&CGA)
+        .into_iter()
+        .chain(greys)
+        .chain(hues)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn palettes_have_expected_number_of_colors() {
+        for palette in BuiltinPalette::iter() {
+            let expected = match palette {
+                BuiltinPalette::WebSafe => 216,
+                BuiltinPalette::Cga => 16,
+                BuiltinPalette::CgaMode4Palette0Low
+                | BuiltinPalette::CgaMode4Palette0High
+                | BuiltinPalette::CgaMode4Palette1Low
+                | BuiltinPalette::CgaMode4Palette1High
+                | BuiltinPalette::CgaMode5Low
+                | BuiltinPalette::CgaMode5High => 4,
+                BuiltinPalette::Ega => 64,
+                BuiltinPalette::Commodore64 => 16,
+                BuiltinPalette::ZxSpectrumNormal | BuiltinPalette::ZxSpectrumBright => 8,
+                // Black appears 10 times in the 64 entries
+                BuiltinPalette::Nes => 55,
+                BuiltinPalette::GameBoy => 4,
+                BuiltinPalette::GameBoyColor => 32768,
+                BuiltinPalette::AmstradCpc => 27,
+                BuiltinPalette::Pico8 => 16,
+                BuiltinPalette::Msx => 15,
+                BuiltinPalette::AppleII => 15,
+                BuiltinPalette::Windows16 => 16,
+                // The darkest and the brightest greys are the CGA black and white,
+                // the last 8 entries of the hardware palette are unused
+                BuiltinPalette::Vga256 => 246,
+            };
+            assert_eq!(colors(palette).len(), expected, "{}", palette);
+        }
+    }
+}
@@ -1,10 +1,27 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::OnceLock,
+};
 
 use egui::{Color32, ColorImage};
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rayon::prelude::*;
 
-use crate::algorithms::{DitheringParameters, PopularityParameters};
+use crate::{
+    algorithms::{
+        AlphaMode, AlphaParameters, BayerMatrixSize, BlueNoiseDitheringParameters, BlueNoiseSize,
+        DistanceMetric, DitheringParameters, DitheringTarget, ErrorDiffusionKernel,
+        ErrorDiffusionParameters, FixedPaletteDithering, FixedPaletteMappingParameters,
+        FixedPaletteParameters, HalftoneParameters, HalftoneSpotFunction, KMeansInitialization,
+        KMeansParameters, MedianCutParameters, NeuQuantParameters, OctreeParameters,
+        OrderedDitheringParameters, PaletteSource, PatternDitheringMethod,
+        PatternDitheringParameters, PopularityParameters, RandomOrderedDitheringParameters,
+        RiemersmaParameters, WuParameters,
+    },
+    color_spaces,
+    palette::Palette,
+};
 
 pub trait ColorQuantizer {
     type Params;
@@ -12,15 +29,57 @@ pub trait ColorQuantizer {
     fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage;
 }
 
+// Algorithms which choose a palette for the image, which can be used also by other algorithms (e.g. dithering)
+pub trait PaletteBuilder {
+    type Params;
+
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette;
+}
+
 struct DitheringCommon;
 
 impl DitheringCommon {
+    // There are always at least 2 levels, black and white
     fn generate_color_levels(k: u8) -> Vec<u8> {
+        let k = k.max(2);
         (0..k)
             .map(|i| ((i as f32) * 255.0 / (k - 1) as f32).round() as u8)
             .collect()
     }
 
+    // Returns the level closest to `value` (linear light intensity from 0 to 1) when compared in linear light
+    fn find_closest_linear_level(value: f32, levels: &[u8]) -> u8 {
+        let idx = levels.partition_point(|&level| color_spaces::srgb_to_linear(level) < value);
+        if idx == 0 {
+            return levels[0];
+        }
+        if idx == levels.len() {
+            return levels[levels.len() - 1];
+        }
+        let prev = levels[idx - 1];
+        let next = levels[idx];
+        if value - color_spaces::srgb_to_linear(prev) <= color_spaces::srgb_to_linear(next) - value
+        {
+            prev
+        } else {
+            next
+        }
+    }
+
+    // Moves the value between its neighbouring levels, so that its position between them is the same
+    // as position of its linear light intensity between linear light intensities of the levels
+    // Thresholding such value chooses levels whose linear average matches the original value
+    fn linear_light_value(value: u8, levels: &[u8]) -> u8 {
+        let col = (value as usize * (levels.len() - 1) / 255).min(levels.len() - 2);
+        let (prev, next) = (levels[col], levels[col + 1]);
+        let prev_linear = color_spaces::srgb_to_linear(prev);
+        let next_linear = color_spaces::srgb_to_linear(next);
+        let fraction = ((color_spaces::srgb_to_linear(value) - prev_linear)
+            / (next_linear - prev_linear))
+            .clamp(0.0, 1.0);
+        (prev as f32 + fraction * (next - prev) as f32).round() as u8
+    }
+
     fn find_closest_level(value: u8, levels: &[u8]) -> u8 {
         if value <= levels[0] {
             return levels[0];
@@ -46,6 +105,375 @@ impl DitheringCommon {
     }
 }
 
+// Colors available in the output image of dithering algorithms
+enum DitheringColors {
+    Levels {
+        r_levels: Vec<u8>,
+        g_levels: Vec<u8>,
+        b_levels: Vec<u8>,
+        linear_light: bool,
+    },
+    Palette(PaletteMatcher),
+}
+
+impl DitheringColors {
+    fn new(
+        params: DitheringParameters,
+        target: DitheringTarget,
+        initial_image: &ColorImage,
+    ) -> DitheringColors {
+        match target {
+            DitheringTarget::Levels => DitheringColors::Levels {
+                r_levels: DitheringCommon::generate_color_levels(params.k_r),
+                g_levels: DitheringCommon::generate_color_levels(params.k_g),
+                b_levels: DitheringCommon::generate_color_levels(params.k_b),
+                linear_light: params.linear_light,
+            },
+            DitheringTarget::Palette(source) => DitheringColors::Palette(PaletteMatcher::new(
+                Palette::build(&source, initial_image).colors,
+                source.metric(),
+            )),
+        }
+    }
+
+    // Returns values in which errors are accumulated, in linear light mode
+    // these are linear light intensities scaled to range from 0 to 255
+    fn working_value(&self, pixel: Color32) -> [f32; 3] {
+        match self {
+            DitheringColors::Levels {
+                linear_light: true, ..
+            } => color_spaces::color_to_linear_rgb(pixel).map(|channel| channel * 255.0),
+            _ => [pixel.r() as f32, pixel.g() as f32, pixel.b() as f32],
+        }
+    }
+
+    // `value` is given in the same space as returned by `working_value`
+    fn find_closest_color(&self, value: [f32; 3]) -> Color32 {
+        let [r, g, b] = value.map(|channel| channel.clamp(0.0, 255.0).round() as u8);
+        match self {
+            DitheringColors::Levels {
+                r_levels,
+                g_levels,
+                b_levels,
+                linear_light: true,
+            } => {
+                let [r, g, b] = value.map(|channel| (channel / 255.0).clamp(0.0, 1.0));
+                Color32::from_rgb(
+                    DitheringCommon::find_closest_linear_level(r, r_levels),
+                    DitheringCommon::find_closest_linear_level(g, g_levels),
+                    DitheringCommon::find_closest_linear_level(b, b_levels),
+                )
+            }
+            DitheringColors::Levels {
+                r_levels,
+                g_levels,
+                b_levels,
+                linear_light: false,
+            } => Color32::from_rgb(
+                DitheringCommon::find_closest_level(r, r_levels),
+                DitheringCommon::find_closest_level(g, g_levels),
+                DitheringCommon::find_closest_level(b, b_levels),
+            ),
+            DitheringColors::Palette(matcher) => {
+                matcher.find_closest_color(Color32::from_rgb(r, g, b))
+            }
+        }
+    }
+}
+
+// Palette which contains all combinations of levels of each channel
+struct PaletteGrid {
+    levels: [Vec<u8>; 3],
+}
+
+// Palette prepared for searching the closest color with given metric,
+// palette colors are converted to the color space of the metric only once
+pub(crate) struct PaletteMatcher {
+    colors: Vec<Color32>,
+    converted: Vec<[f32; 3]>,
+    metric: DistanceMetric,
+    // Used only with RGB Euclidean metric, which can be searched channel by channel
+    grid: Option<PaletteGrid>,
+    // K-d tree of converted colors, used for big palettes which aren't searched in the grid
+    // Each range of indices has its splitting color in the middle, lower half before and upper half after it
+    tree: Option<Vec<usize>>,
+    // Biggest chroma of converted palette colors, it bounds weights of CIEDE2000
+    max_chroma: f32,
+}
+
+impl PaletteMatcher {
+    // Palettes this big are searched in the k-d tree, smaller ones are searched linearly
+    const TREE_SEARCH_MIN_COLORS: usize = 512;
+    // Lower bounds are slightly decreased, so that rounding errors never prune the closest color
+    const LOWER_BOUND_MARGIN: f32 = 0.99;
+
+    pub(crate) fn new(colors: Vec<Color32>, metric: DistanceMetric) -> PaletteMatcher {
+        let converted: Vec<[f32; 3]> = colors
+            .iter()
+            .map(|&color| Self::convert(color, metric))
+            .collect();
+        let grid = match metric {
+            DistanceMetric::RgbEuclidean => Self::grid(&colors),
+            _ => None,
+        };
+        let tree = (grid.is_none() && colors.len() >= Self::TREE_SEARCH_MIN_COLORS).then(|| {
+            let mut tree: Vec<usize> = (0..colors.len()).collect();
+            Self::build_tree(&converted, &mut tree, 0);
+            tree
+        });
+        let max_chroma = converted
+            .iter()
+            .map(|&[_, a, b]| (a * a + b * b).sqrt())
+            .fold(0.0, f32::max);
+        PaletteMatcher {
+            colors,
+            converted,
+            metric,
+            grid,
+            tree,
+            max_chroma,
+        }
+    }
+
+    // Large palettes like Game Boy Color's 15-bit colors are usually grids, which would be slow to search linearly
+    fn grid(colors: &[Color32]) -> Option<PaletteGrid> {
+        let levels = [0, 1, 2].map(|channel| {
+            let mut values: Vec<u8> = colors
+                .iter()
+                .map(|color| color.to_array()[channel])
+                .collect();
+            values.sort_unstable();
+            values.dedup();
+            values
+        });
+        // Unique colors are a subset of all combinations of levels, so they are equal when their counts are
+        let unique_colors = colors.iter().collect::<HashSet<_>>().len();
+        let combinations: usize = levels.iter().map(Vec::len).product();
+        if unique_colors != combinations {
+            return None;
+        }
+        Some(PaletteGrid { levels })
+    }
+
+    // Splits colors by the median of channels taken in turns
+    fn build_tree(converted: &[[f32; 3]], ids: &mut [usize], depth: usize) {
+        if ids.len() <= 1 {
+            return;
+        }
+        let channel = depth % 3;
+        let middle = ids.len() / 2;
+        ids.select_nth_unstable_by(middle, |&lhs, &rhs| {
+            converted[lhs][channel].total_cmp(&converted[rhs][channel])
+        });
+        let (lower, upper) = ids.split_at_mut(middle);
+        Self::build_tree(converted, lower, depth + 1);
+        Self::build_tree(converted, &mut upper[1..], depth + 1);
+    }
+
+    fn convert(color: Color32, metric: DistanceMetric) -> [f32; 3] {
+        Self::convert_value(
+            [color.r() as f32, color.g() as f32, color.b() as f32],
+            metric,
+        )
+    }
+
+    // Converts color given as sRGB values (which don't have to be integers)
+    fn convert_value(value: [f32; 3], metric: DistanceMetric) -> [f32; 3] {
+        let to_color = || {
+            let [r, g, b] = value.map(|channel| channel.clamp(0.0, 255.0).round() as u8);
+            Color32::from_rgb(r, g, b)
+        };
+        match metric {
+            DistanceMetric::RgbEuclidean | DistanceMetric::Redmean => value,
+            DistanceMetric::Cie76 | DistanceMetric::Cie94 | DistanceMetric::Ciede2000 => {
+                color_spaces::color_to_lab(to_color())
+            }
+            DistanceMetric::OkLab => color_spaces::color_to_oklab(to_color()),
+        }
+    }
+
+    // Distance between converted colors, it is only used for comparisons so it doesn't have to be a square root
+    fn distance(&self, pixel: [f32; 3], palette_color: [f32; 3]) -> f32 {
+        match self.metric {
+            DistanceMetric::RgbEuclidean | DistanceMetric::Cie76 | DistanceMetric::OkLab => {
+                color_spaces::squared_euclidean(pixel, palette_color)
+            }
+            DistanceMetric::Redmean => color_spaces::redmean_squared(pixel, palette_color),
+            DistanceMetric::Cie94 => color_spaces::cie94(pixel, palette_color),
+            DistanceMetric::Ciede2000 => color_spaces::ciede2000(pixel, palette_color),
+        }
+    }
+
+    // Weights of squared channel differences, whose weighted sum is never bigger than the squared distance
+    // from the pixel (or the distance itself, for metrics whose distance is squared)
+    fn lower_bound_weights(&self, pixel: [f32; 3]) -> [f32; 3] {
+        let weights = match self.metric {
+            DistanceMetric::RgbEuclidean | DistanceMetric::Cie76 | DistanceMetric::OkLab => {
+                [1.0; 3]
+            }
+            // Weights of red and blue are at least 2
+            DistanceMetric::Redmean => [2.0, 4.0, 2.0],
+            DistanceMetric::Cie94 => color_spaces::cie94_lower_bound_weights(pixel),
+            DistanceMetric::Ciede2000 => {
+                color_spaces::ciede2000_lower_bound_weights(pixel, self.max_chroma)
+            }
+        };
+        weights.map(|weight| weight * Self::LOWER_BOUND_MARGIN)
+    }
+
+    // Smallest possible distance to colors whose channels differ from the pixel's ones at least by `differences`
+    fn distance_lower_bound(&self, weights: [f32; 3], differences: [f32; 3]) -> f32 {
+        let sum: f32 = (0..3)
+            .map(|channel| weights[channel] * differences[channel] * differences[channel])
+            .sum();
+        match self.metric {
+            DistanceMetric::Cie94 | DistanceMetric::Ciede2000 => sum.sqrt(),
+            _ => sum,
+        }
+    }
+
+    pub(crate) fn find_closest_color(&self, pixel: Color32) -> Color32 {
+        if let Some(grid) = &self.grid {
+            let [r_levels, g_levels, b_levels] = &grid.levels;
+            return Color32::from_rgb(
+                DitheringCommon::find_closest_level(pixel.r(), r_levels),
+                DitheringCommon::find_closest_level(pixel.g(), g_levels),
+                DitheringCommon::find_closest_level(pixel.b(), b_levels),
+            );
+        }
+        if let Some(tree) = &self.tree {
+            return self.find_closest_tree_color(pixel, tree);
+        }
+        self.find_closest_linear_color(pixel)
+    }
+
+    fn find_closest_linear_color(&self, pixel: Color32) -> Color32 {
+        let converted_pixel = Self::convert(pixel, self.metric);
+        self.converted
+            .iter()
+            .map(|&color| self.distance(converted_pixel, color))
+            .enumerate()
+            .min_by(|(_, lhs_dist), (_, rhs_dist)| {
+                lhs_dist
+                    .partial_cmp(rhs_dist)
+                    .expect("Colors distances should always be comparable")
+            })
+            .map(|(id, _)| self.colors[id])
+            .expect("Color should never be empty")
+    }
+
+    // The result is the same as of the linear search, ties are resolved in favour of the lower index too
+    fn find_closest_tree_color(&self, pixel: Color32, tree: &[usize]) -> Color32 {
+        let converted_pixel = Self::convert(pixel, self.metric);
+        let weights = self.lower_bound_weights(converted_pixel);
+        let mut best = (f32::MAX, usize::MAX);
+        self.search_tree(tree, 0, converted_pixel, weights, [0.0; 3], &mut best);
+        self.colors[best.1]
+    }
+
+    // `differences` are the smallest differences of channels between the pixel and colors in `ids`
+    fn search_tree(
+        &self,
+        ids: &[usize],
+        depth: usize,
+        pixel: [f32; 3],
+        weights: [f32; 3],
+        differences: [f32; 3],
+        best: &mut (f32, usize),
+    ) {
+        if ids.is_empty() {
+            return;
+        }
+        let middle = ids.len() / 2;
+        let id = ids[middle];
+        // CIEDE2000 is expensive, so colors which are certainly further are skipped early
+        let skip = self.metric == DistanceMetric::Ciede2000
+            && color_spaces::ciede2000_lower_bound(pixel, self.converted[id])
+                * Self::LOWER_BOUND_MARGIN
+                > best.0;
+        if !skip {
+            let distance = self.distance(pixel, self.converted[id]);
+            if distance < best.0 || (distance == best.0 && id < best.1) {
+                *best = (distance, id);
+            }
+        }
+        let channel = depth % 3;
+        let difference = pixel[channel] - self.converted[id][channel];
+        let (near, far) = if difference < 0.0 {
+            (&ids[..middle], &ids[middle + 1..])
+        } else {
+            (&ids[middle + 1..], &ids[..middle])
+        };
+        self.search_tree(near, depth + 1, pixel, weights, differences, best);
+        let mut far_differences = differences;
+        far_differences[channel] = difference.abs();
+        // Colors on the other side of the split are at least as far in this channel as the splitting color
+        if self.distance_lower_bound(weights, far_differences) <= best.0 {
+            self.search_tree(far, depth + 1, pixel, weights, far_differences, best);
+        }
+    }
+}
+
+struct PaletteCommon;
+
+impl PaletteCommon {
+    // Pixels with zero alpha are masked out (see `AlphaQuantizer::opaque_image`), they don't affect palettes
+    fn is_masked(pixel: Color32) -> bool {
+        pixel.a() == 0
+    }
+
+    // Palette has at least one color even if every pixel is masked, so that pixels can always be mapped
+    fn non_empty(colors: Vec<Color32>) -> Vec<Color32> {
+        if colors.is_empty() {
+            vec![Color32::BLACK]
+        } else {
+            colors
+        }
+    }
+
+    // Returns unique colors of the image with number of their occurrences, sorted by color
+    // Sorting makes algorithms built on top of it deterministic, because HashMap iteration order is random
+    fn count_colors(initial_image: &ColorImage) -> Vec<(Color32, usize)> {
+        let mut colors_count = HashMap::<Color32, usize>::new();
+        for &pixel in &initial_image.pixels {
+            if !Self::is_masked(pixel) {
+                *colors_count.entry(pixel).or_insert(0) += 1;
+            }
+        }
+        let mut colors: Vec<(Color32, usize)> = colors_count.into_iter().collect();
+        colors.sort_unstable_by_key(|&(color, _)| color.to_array());
+        colors
+    }
+
+    // Replaces each pixel of the image with the closest color from the palette
+    fn map_to_palette(
+        initial_image: &ColorImage,
+        colors: Vec<Color32>,
+        metric: DistanceMetric,
+    ) -> ColorImage {
+        let matcher = PaletteMatcher::new(colors, metric);
+        let output_pixels: Vec<_> = initial_image
+            .pixels
+            .par_chunks(256)
+            .flat_map(|chunk| {
+                // Perceptual metrics are expensive, so results are reused for repeated colors within a chunk
+                let mut closest = HashMap::<Color32, Color32>::new();
+                chunk
+                    .iter()
+                    .flat_map(|&pixel| {
+                        closest
+                            .entry(pixel)
+                            .or_insert_with(|| matcher.find_closest_color(pixel))
+                            .to_array()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let size = initial_image.size;
+        ColorImage::from_rgba_unmultiplied(size, output_pixels.as_slice())
+    }
+}
+
 pub struct AverageDitheringColorQuantizer;
 
 impl ColorQuantizer for AverageDitheringColorQuantizer {
@@ -62,6 +490,13 @@ impl ColorQuantizer for AverageDitheringColorQuantizer {
                 chunk
                     .iter()
                     .flat_map(|&pixel| {
+                        if params.linear_light {
+                            let [r, g, b] = color_spaces::color_to_linear_rgb(pixel);
+                            let r = DitheringCommon::find_closest_linear_level(r, &r_levels);
+                            let g = DitheringCommon::find_closest_linear_level(g, &g_levels);
+                            let b = DitheringCommon::find_closest_linear_level(b, &b_levels);
+                            return egui::Color32::from_rgb(r, g, b).to_array();
+                        }
                         let r = DitheringCommon::find_closest_level(pixel.r(), &r_levels);
                         let g = DitheringCommon::find_closest_level(pixel.g(), &g_levels);
                         let b = DitheringCommon::find_closest_level(pixel.b(), &b_levels);
@@ -79,35 +514,22 @@ pub struct PopularityAlgorithmColorQuantizer;
 
 impl PopularityAlgorithmColorQuantizer {
     fn find_most_popular_k_colors(initial_image: &ColorImage, k: usize) -> Vec<Color32> {
-        let mut colors_count = HashMap::<Color32, usize>::new();
-        for pixel in &initial_image.pixels {
-            *colors_count.entry(*pixel).or_insert(0) += 1;
-        }
-        let mut colors_vec: Vec<(Color32, usize)> = colors_count.into_iter().collect();
-        colors_vec.sort_by(|&lhs, &rhs| rhs.1.cmp(&lhs.1));
+        let mut colors_vec = PaletteCommon::count_colors(initial_image);
+        // Stable sort keeps colors with the same count in deterministic order
+        colors_vec.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
         colors_vec.into_iter().take(k).map(|c| c.0).collect()
     }
+}
 
-    fn find_closest_color(pixel: Color32, colors: &[Color32]) -> Color32 {
-        colors
-            .iter()
-            .min_by(|&lhs, &rhs| {
-                let lhs_dist = Self::colors_distance(pixel, *lhs);
-                let rhs_dist = Self::colors_distance(pixel, *rhs);
-                lhs_dist
-                    .partial_cmp(&rhs_dist)
-                    .expect("Colors distances should always be comparable")
-            })
-            .copied()
-            .expect("Color should never be empty")
-    }
-
-    fn colors_distance(lhs: Color32, rhs: Color32) -> f32 {
-        let r_diff = lhs.r() as f32 - rhs.r() as f32;
-        let g_diff = lhs.g() as f32 - rhs.g() as f32;
-        let b_diff = lhs.b() as f32 - rhs.b() as f32;
+impl PaletteBuilder for PopularityAlgorithmColorQuantizer {
+    type Params = PopularityParameters;
 
-        r_diff * r_diff + g_diff * g_diff + b_diff * b_diff
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette {
+        // Empty palette couldn't be mapped to
+        Palette::new(PaletteCommon::non_empty(Self::find_most_popular_k_colors(
+            initial_image,
+            params.k.max(1),
+        )))
     }
 }
 
@@ -115,302 +537,2753 @@ impl ColorQuantizer for PopularityAlgorithmColorQuantizer {
     type Params = PopularityParameters;
 
     fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
-        let colors = Self::find_most_popular_k_colors(initial_image, params.k);
-        let output_pixesl: Vec<_> = initial_image
-            .pixels
-            .par_chunks(256)
-            .flat_map(|chunk| {
-                chunk
-                    .iter()
-                    .flat_map(|&pixel| Self::find_closest_color(pixel, &colors).to_array())
-                    .collect::<Vec<_>>()
-            })
-            .collect();
-        let size = initial_image.size;
-        ColorImage::from_rgba_unmultiplied(size, output_pixesl.as_slice())
+        let palette = Self::build_palette(params, initial_image);
+        PaletteCommon::map_to_palette(initial_image, palette.colors, params.metric)
     }
 }
 
-pub struct ErrorDiffusionDitheringColorQuantizer;
+struct ColorBox {
+    // Unique colors inside the box together with number of their occurrences
+    colors: Vec<(Color32, usize)>,
+}
 
-impl ErrorDiffusionDitheringColorQuantizer {
-    fn find_closest_level_and_diff(value: u8, levels: &[u8]) -> (u8, f32) {
-        let level = DitheringCommon::find_closest_level(value, levels);
-        let diff = value as f32 - level as f32;
-        (level, diff)
+impl ColorBox {
+    fn channel_value(color: Color32, channel: usize) -> u8 {
+        match channel {
+            0 => color.r(),
+            1 => color.g(),
+            _ => color.b(),
+        }
     }
 
-    fn get_color_with_err(
-        color: Color32,
-        error: f32,
-        r_diff: f32,
-        g_diff: f32,
-        b_diff: f32,
-    ) -> Color32 {
-        let new_r = color.r() as f32 + error * r_diff;
-        let new_g = color.g() as f32 + error * g_diff;
-        let new_b = color.b() as f32 + error * b_diff;
-        Color32::from_rgb(new_r as u8, new_g as u8, new_b as u8)
+    fn channel_range(&self, channel: usize) -> u8 {
+        let (min, max) = self
+            .colors
+            .iter()
+            .map(|&(color, _)| Self::channel_value(color, channel))
+            .fold((u8::MAX, u8::MIN), |(min, max), value| {
+                (min.min(value), max.max(value))
+            });
+        max.saturating_sub(min)
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn add_error(
-        output_pixels: &mut [Color32],
-        row: usize,
-        col: usize,
-        width: usize,
-        error: f32,
-        r_diff: f32,
-        g_diff: f32,
-        b_diff: f32,
-    ) {
-        let id = row * width + col;
-        if id < output_pixels.len() {
-            let color = output_pixels[id];
-            output_pixels[id] = Self::get_color_with_err(color, error, r_diff, g_diff, b_diff);
+    // Returns channel with the biggest range of values and that range
+    fn longest_axis(&self) -> (usize, u8) {
+        (0..3)
+            .map(|channel| (channel, self.channel_range(channel)))
+            .max_by_key(|&(_, range)| range)
+            .expect("There are always three channels")
+    }
+
+    fn can_be_split(&self) -> bool {
+        self.colors.len() > 1
+    }
+
+    // Splits box along given channel, so that both halves contain (roughly) the same number of pixels
+    fn split(mut self, channel: usize) -> (ColorBox, ColorBox) {
+        // Stable sort keeps the order deterministic for colors with the same channel value
+        self.colors
+            .sort_by_key(|&(color, _)| Self::channel_value(color, channel));
+        let total: usize = self.colors.iter().map(|&(_, count)| count).sum();
+        let mut accumulated = 0;
+        let mut split_at = self.colors.len() / 2;
+        for (i, &(_, count)) in self.colors.iter().enumerate() {
+            accumulated += count;
+            if accumulated * 2 >= total {
+                split_at = i + 1;
+                break;
+            }
         }
+        let split_at = split_at.clamp(1, self.colors.len() - 1);
+        let upper = self.colors.split_off(split_at);
+        (self, ColorBox { colors: upper })
     }
 
-    const ERROR_WAGE_MATRIX: [f32; 4] = [0.4375, 0.1875, 0.3125, 0.0625];
+    fn average_color(&self) -> Color32 {
+        let (r, g, b, total) = self.colors.iter().fold(
+            (0u64, 0u64, 0u64, 0u64),
+            |(r, g, b, total), &(color, count)| {
+                let count = count as u64;
+                (
+                    r + color.r() as u64 * count,
+                    g + color.g() as u64 * count,
+                    b + color.b() as u64 * count,
+                    total + count,
+                )
+            },
+        );
+        let avg = |sum: u64| ((sum as f64) / (total as f64)).round() as u8;
+        Color32::from_rgb(avg(r), avg(g), avg(b))
+    }
 }
 
-impl ColorQuantizer for ErrorDiffusionDitheringColorQuantizer {
-    type Params = DitheringParameters;
-
-    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
-        let r_levels = DitheringCommon::generate_color_levels(params.k_r);
-        let g_levels = DitheringCommon::generate_color_levels(params.k_g);
-        let b_levels = DitheringCommon::generate_color_levels(params.k_b);
+pub struct MedianCutColorQuantizer;
 
-        let size = initial_image.size;
+impl MedianCutColorQuantizer {
+    fn find_palette(initial_image: &ColorImage, k: usize) -> Vec<Color32> {
+        let colors = PaletteCommon::count_colors(initial_image);
+        // Average of an empty box is undefined
+        if colors.is_empty() {
+            return vec![];
+        }
+        let mut boxes = vec![ColorBox { colors }];
+        while boxes.len() < k {
+            // We always split the box with the longest axis among all boxes
+            let Some((box_id, channel)) = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, color_box)| color_box.can_be_split())
+                .map(|(id, color_box)| (id, color_box.longest_axis()))
+                .max_by_key(|&(_, (_, range))| range)
+                .map(|(id, (channel, _))| (id, channel))
+            else {
+                break;
+            };
+            let (lower, upper) = boxes.swap_remove(box_id).split(channel);
+            boxes.push(lower);
+            boxes.push(upper);
+        }
 
-        let mut output_pixels = initial_image.pixels.clone();
-        for i in 0..output_pixels.len() {
-            let pixel = output_pixels[i];
-            let (r, r_diff) = Self::find_closest_level_and_diff(pixel.r(), &r_levels);
-            let (g, g_diff) = Self::find_closest_level_and_diff(pixel.g(), &g_levels);
-            let (b, b_diff) = Self::find_closest_level_and_diff(pixel.b(), &b_levels);
-            output_pixels[i] = Color32::from_rgb(r, g, b);
-
-            let row = i / size[0];
-            let column = i - row * size[0];
-
-            Self::add_error(
-                &mut output_pixels,
-                row,
-                column + 1,
-                size[0],
-                Self::ERROR_WAGE_MATRIX[0],
-                r_diff,
-                g_diff,
-                b_diff,
-            );
+        boxes.iter().map(ColorBox::average_color).collect()
+    }
+}
 
-            if column > 0 {
-                Self::add_error(
-                    &mut output_pixels,
-                    row + 1,
-                    column - 1,
-                    size[0],
-                    Self::ERROR_WAGE_MATRIX[1],
-                    r_diff,
-                    g_diff,
-                    b_diff,
-                );
-            }
+impl PaletteBuilder for MedianCutColorQuantizer {
+    type Params = MedianCutParameters;
 
-            Self::add_error(
-                &mut output_pixels,
-                row + 1,
-                column,
-                size[0],
-                Self::ERROR_WAGE_MATRIX[2],
-                r_diff,
-                g_diff,
-                b_diff,
-            );
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette {
+        // Empty palette couldn't be mapped to
+        Palette::new(PaletteCommon::non_empty(Self::find_palette(
+            initial_image,
+            params.k.max(1),
+        )))
+    }
+}
 
-            Self::add_error(
-                &mut output_pixels,
-                row + 1,
-                column + 1,
-                size[0],
-                Self::ERROR_WAGE_MATRIX[3],
-                r_diff,
-                g_diff,
-                b_diff,
-            );
-        }
+impl ColorQuantizer for MedianCutColorQuantizer {
+    type Params = MedianCutParameters;
 
-        ColorImage::from_rgba_unmultiplied(
-            size,
-            output_pixels
-                .iter()
-                .flat_map(|&p| p.to_array())
-                .collect::<Vec<_>>()
-                .as_slice(),
-        )
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let palette = Self::build_palette(params, initial_image);
+        PaletteCommon::map_to_palette(initial_image, palette.colors, params.metric)
     }
 }
 
-trait OrderedDitheringCommon {
-    fn get_color(value: u8, levels: &[u8], matrix: &[Vec<u32>], x: usize, y: usize, n: usize)
-        -> u8;
+#[derive(Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    r_sum: u64,
+    g_sum: u64,
+    b_sum: u64,
+    pixel_count: u64,
+    is_leaf: bool,
+    palette_index: usize,
+}
 
-    const POSSIBLE_N: [u8; 7] = [2, 3, 4, 6, 8, 12, 16];
+struct Octree {
+    // All nodes are stored in one arena, root is always at index 0
+    nodes: Vec<OctreeNode>,
+    // Indices of nodes freed during reduction, reused when creating new ones
+    free_nodes: Vec<usize>,
+    // For each level, inner nodes that can be reduced (merged with their children)
+    reducible: Vec<Vec<usize>>,
+    leaf_count: usize,
+    max_depth: usize,
+}
 
-    fn find_n(k: u8) -> u8 {
-        *Self::POSSIBLE_N
+impl Octree {
+    // Upper bound for number of leaves while inserting pixels, keeps memory usage bounded for huge images
+    const MAX_LEAVES_DURING_INSERTION: usize = 1 << 14;
+
+    fn new(max_depth: usize) -> Octree {
+        let mut octree = Octree {
+            nodes: vec![],
+            free_nodes: vec![],
+            reducible: vec![vec![]; max_depth],
+            leaf_count: 0,
+            max_depth,
+        };
+        octree.create_node(0);
+        octree
+    }
+
+    fn child_index(color: Color32, level: usize) -> usize {
+        let shift = 7 - level;
+        let r = ((color.r() >> shift) & 1) as usize;
+        let g = ((color.g() >> shift) & 1) as usize;
+        let b = ((color.b() >> shift) & 1) as usize;
+        (r << 2) | (g << 1) | b
+    }
+
+    fn create_node(&mut self, level: usize) -> usize {
+        let node = OctreeNode {
+            is_leaf: level == self.max_depth,
+            ..Default::default()
+        };
+        let id = match self.free_nodes.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        if level == self.max_depth {
+            self.leaf_count += 1;
+        } else {
+            self.reducible[level].push(id);
+        }
+        id
+    }
+
+    fn insert(&mut self, color: Color32) {
+        let mut node_id = 0;
+        let mut level = 0;
+        while !self.nodes[node_id].is_leaf {
+            let child = Self::child_index(color, level);
+            node_id = match self.nodes[node_id].children[child] {
+                Some(child_id) => child_id,
+                None => {
+                    let child_id = self.create_node(level + 1);
+                    self.nodes[node_id].children[child] = Some(child_id);
+                    child_id
+                }
+            };
+            level += 1;
+        }
+        let node = &mut self.nodes[node_id];
+        node.r_sum += color.r() as u64;
+        node.g_sum += color.g() as u64;
+        node.b_sum += color.b() as u64;
+        node.pixel_count += 1;
+    }
+
+    fn subtree_pixel_count(&self, node_id: usize) -> u64 {
+        self.nodes[node_id]
+            .children
             .iter()
-            .find(|&&n| n as u32 * n as u32 * (k as u32 - 1) >= 256)
-            .unwrap_or(Self::POSSIBLE_N.last().unwrap())
+            .flatten()
+            .map(|&child_id| self.nodes[child_id].pixel_count)
+            .sum()
     }
 
-    fn generate_matrix(n: u8) -> Vec<Vec<u32>> {
-        let mut matrix: Vec<Vec<u32>> = vec![vec![0; n as usize]; n as usize];
-        if n == 2 {
-            matrix[0][0] = 0;
-            matrix[0][1] = 2;
-            matrix[1][0] = 3;
-            matrix[1][1] = 1;
-            return matrix;
+    // Merges children of the most recently created inner node on the deepest level into it
+    fn reduce_last(&mut self) {
+        if let Some(level) = (0..self.max_depth)
+            .rev()
+            .find(|&level| !self.reducible[level].is_empty())
+        {
+            let node_id = self.reducible[level]
+                .pop()
+                .expect("Level should not be empty");
+            self.merge_children(node_id);
         }
-        if n == 3 {
-            matrix[0][0] = 6;
-            matrix[0][1] = 8;
-            matrix[0][2] = 4;
-            matrix[1][0] = 1;
-            matrix[1][1] = 0;
-            matrix[1][2] = 3;
-            matrix[2][0] = 5;
-            matrix[2][1] = 2;
-            matrix[2][2] = 7;
-            return matrix;
+    }
+
+    // Reduces the tree until at most `k` leaves remain
+    // On each level, nodes representing the least pixels are merged first
+    fn reduce_to(&mut self, k: usize) {
+        for level in (0..self.max_depth).rev() {
+            if self.leaf_count <= k {
+                break;
+            }
+            // All deeper levels are already reduced, so children of nodes on this level are leaves
+            // and pixel counts don't change until the whole level is processed
+            let mut nodes = std::mem::take(&mut self.reducible[level]);
+            nodes.sort_by_key(|&node_id| {
+                (
+                    std::cmp::Reverse(self.subtree_pixel_count(node_id)),
+                    node_id,
+                )
+            });
+            while self.leaf_count > k {
+                let Some(node_id) = nodes.pop() else {
+                    break;
+                };
+                self.merge_children(node_id);
+            }
+            self.reducible[level] = nodes;
         }
+    }
 
-        let half_matrix = Self::generate_matrix(n / 2);
-        let half_n = n as usize / 2;
-        for i in 0..half_n {
-            for j in 0..half_n {
-                matrix[i][j] = 4 * half_matrix[i][j];
-                matrix[i][j + half_n] = 4 * half_matrix[i][j] + 2;
-                matrix[i + half_n][j] = 4 * half_matrix[i][j] + 3;
-                matrix[i + half_n][j + half_n] = 4 * half_matrix[i][j] + 1;
+    fn merge_children(&mut self, node_id: usize) {
+        let children = std::mem::take(&mut self.nodes[node_id].children);
+        let mut children_count = 0;
+        for child_id in children.into_iter().flatten() {
+            let child = &self.nodes[child_id];
+            let (r, g, b, count) = (child.r_sum, child.g_sum, child.b_sum, child.pixel_count);
+            let node = &mut self.nodes[node_id];
+            node.r_sum += r;
+            node.g_sum += g;
+            node.b_sum += b;
+            node.pixel_count += count;
+            self.free_nodes.push(child_id);
+            children_count += 1;
+        }
+        self.nodes[node_id].is_leaf = true;
+        // Children are replaced with a single leaf
+        self.leaf_count = self.leaf_count + 1 - children_count;
+    }
+
+    // Assigns palette index to every leaf and returns palette built from average colors of leaves
+    fn build_palette(&mut self) -> Vec<Color32> {
+        let mut palette = vec![];
+        let mut stack = vec![0];
+        while let Some(node_id) = stack.pop() {
+            let node = &mut self.nodes[node_id];
+            if node.is_leaf {
+                if node.pixel_count > 0 {
+                    let avg = |sum: u64| ((sum as f64) / (node.pixel_count as f64)).round() as u8;
+                    palette.push(Color32::from_rgb(
+                        avg(node.r_sum),
+                        avg(node.g_sum),
+                        avg(node.b_sum),
+                    ));
+                    node.palette_index = palette.len() - 1;
+                }
+            } else {
+                stack.extend(node.children.iter().flatten());
             }
         }
-        matrix
+        palette
     }
 
-    fn ordered_dithering_output_image(
-        params: DitheringParameters,
-        initial_image: &ColorImage,
-    ) -> ColorImage {
-        let r_levels = DitheringCommon::generate_color_levels(params.k_r);
-        let g_levels = DitheringCommon::generate_color_levels(params.k_g);
-        let b_levels = DitheringCommon::generate_color_levels(params.k_b);
+    fn find_palette_index(&self, color: Color32) -> usize {
+        let mut node_id = 0;
+        let mut level = 0;
+        while !self.nodes[node_id].is_leaf {
+            node_id = self.nodes[node_id].children[Self::child_index(color, level)]
+                .expect("Every pixel color should have its path in the tree");
+            level += 1;
+        }
+        self.nodes[node_id].palette_index
+    }
+}
 
-        let n_r = Self::find_n(params.k_r);
-        let m_r = Self::generate_matrix(n_r);
-        let n_g = Self::find_n(params.k_g);
-        let m_g = Self::generate_matrix(n_g);
-        let n_b = Self::find_n(params.k_b);
-        let m_b = Self::generate_matrix(n_b);
+pub struct OctreeColorQuantizer;
 
-        const CHUNK_SIZE: usize = 512;
+impl OctreeColorQuantizer {
+    fn build_tree(params: OctreeParameters, initial_image: &ColorImage) -> Octree {
+        let max_depth = (params.max_depth as usize).clamp(1, 8);
+        let k = params.k.max(1);
 
-        let size = initial_image.size;
+        let mut octree = Octree::new(max_depth);
+        let max_leaves = k.max(Octree::MAX_LEAVES_DURING_INSERTION);
+        for &pixel in &initial_image.pixels {
+            if PaletteCommon::is_masked(pixel) {
+                continue;
+            }
+            octree.insert(pixel);
+            while octree.leaf_count > max_leaves {
+                octree.reduce_last();
+            }
+        }
+        octree.reduce_to(k);
+        octree
+    }
+}
 
-        let output_pixesl: Vec<_> = initial_image
-            .pixels
-            .par_chunks(CHUNK_SIZE)
-            .enumerate()
-            .flat_map(|(chunk_id, chunk)| {
+impl PaletteBuilder for OctreeColorQuantizer {
+    type Params = OctreeParameters;
+
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette {
+        Palette::new(PaletteCommon::non_empty(
+            Self::build_tree(params, initial_image).build_palette(),
+        ))
+    }
+}
+
+impl ColorQuantizer for OctreeColorQuantizer {
+    type Params = OctreeParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let mut octree = Self::build_tree(params, initial_image);
+        let palette = octree.build_palette();
+        if params.metric != DistanceMetric::RgbEuclidean {
+            return PaletteCommon::map_to_palette(initial_image, palette, params.metric);
+        }
+
+        let output_pixels: Vec<_> = initial_image
+            .pixels
+            .par_chunks(256)
+            .flat_map(|chunk| {
+                chunk
+                    .iter()
+                    .flat_map(|&pixel| {
+                        // Masked pixels aren't in the tree, their color is replaced by transparency anyway
+                        if PaletteCommon::is_masked(pixel) {
+                            return Color32::TRANSPARENT.to_array();
+                        }
+                        palette[octree.find_palette_index(pixel)].to_array()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let size = initial_image.size;
+        ColorImage::from_rgba_unmultiplied(size, output_pixels.as_slice())
+    }
+}
+
+pub struct KMeansColorQuantizer;
+
+impl KMeansColorQuantizer {
+    fn to_point(color: Color32) -> [f32; 3] {
+        [color.r() as f32, color.g() as f32, color.b() as f32]
+    }
+
+    fn points_distance(lhs: [f32; 3], rhs: [f32; 3]) -> f32 {
+        let r_diff = lhs[0] - rhs[0];
+        let g_diff = lhs[1] - rhs[1];
+        let b_diff = lhs[2] - rhs[2];
+
+        r_diff * r_diff + g_diff * g_diff + b_diff * b_diff
+    }
+
+    fn find_closest_centroid(point: [f32; 3], centroids: &[[f32; 3]]) -> usize {
+        centroids
+            .iter()
+            .map(|&centroid| Self::points_distance(point, centroid))
+            .enumerate()
+            .min_by(|(_, lhs), (_, rhs)| {
+                lhs.partial_cmp(rhs)
+                    .expect("Colors distances should always be comparable")
+            })
+            .map(|(id, _)| id)
+            .expect("Centroids should never be empty")
+    }
+
+    // Picks index of the color with probability proportional to its weight
+    fn pick_weighted(weights: &[f64], rng: &mut StdRng) -> usize {
+        let total: f64 = weights.iter().sum();
+        let mut target = rng.gen::<f64>() * total;
+        for (id, &weight) in weights.iter().enumerate() {
+            if target < weight {
+                return id;
+            }
+            target -= weight;
+        }
+        // Might happen only because of floating point rounding
+        weights
+            .iter()
+            .rposition(|&weight| weight > 0.0)
+            .unwrap_or(weights.len() - 1)
+    }
+
+    fn initial_centroids(
+        params: &KMeansParameters,
+        k: usize,
+        colors: &[(Color32, usize)],
+        initial_image: &ColorImage,
+        rng: &mut StdRng,
+    ) -> Vec<[f32; 3]> {
+        match params.initialization {
+            KMeansInitialization::Random => rand::seq::index::sample(rng, colors.len(), k)
+                .into_iter()
+                .map(|id| Self::to_point(colors[id].0))
+                .collect(),
+            KMeansInitialization::KMeansPlusPlus => {
+                let counts: Vec<f64> = colors.iter().map(|&(_, count)| count as f64).collect();
+                let first = colors[Self::pick_weighted(&counts, rng)].0;
+                let mut centroids = vec![Self::to_point(first)];
+                let mut distances: Vec<f32> = colors
+                    .par_iter()
+                    .map(|&(color, _)| Self::points_distance(Self::to_point(color), centroids[0]))
+                    .collect();
+                while centroids.len() < k {
+                    let weights: Vec<f64> = distances
+                        .iter()
+                        .zip(&counts)
+                        .map(|(&distance, &count)| distance as f64 * count)
+                        .collect();
+                    let centroid = Self::to_point(colors[Self::pick_weighted(&weights, rng)].0);
+                    centroids.push(centroid);
+                    distances.par_iter_mut().zip(colors.par_iter()).for_each(
+                        |(distance, &(color, _))| {
+                            let new_distance =
+                                Self::points_distance(Self::to_point(color), centroid);
+                            *distance = distance.min(new_distance);
+                        },
+                    );
+                }
+                centroids
+            }
+            KMeansInitialization::Popularity => {
+                PopularityAlgorithmColorQuantizer::find_most_popular_k_colors(initial_image, k)
+                    .into_iter()
+                    .map(Self::to_point)
+                    .collect()
+            }
+        }
+    }
+
+    fn find_palette(params: &KMeansParameters, initial_image: &ColorImage) -> Vec<Color32> {
+        let colors = PaletteCommon::count_colors(initial_image);
+        if colors.is_empty() {
+            return vec![Color32::BLACK];
+        }
+        let k = params.k.clamp(1, colors.len());
+
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        let mut centroids = Self::initial_centroids(params, k, &colors, initial_image, &mut rng);
+
+        for _ in 0..params.max_iterations {
+            // Sums are kept as integers, so the result doesn't depend on the order in which rayon combines them
+            let sums = colors
+                .par_iter()
+                .fold(
+                    || vec![[0u64; 4]; k],
+                    |mut sums, &(color, count)| {
+                        let id = Self::find_closest_centroid(Self::to_point(color), &centroids);
+                        let count = count as u64;
+                        sums[id][0] += color.r() as u64 * count;
+                        sums[id][1] += color.g() as u64 * count;
+                        sums[id][2] += color.b() as u64 * count;
+                        sums[id][3] += count;
+                        sums
+                    },
+                )
+                .reduce(
+                    || vec![[0u64; 4]; k],
+                    |mut lhs, rhs| {
+                        for (l, r) in lhs.iter_mut().zip(&rhs) {
+                            for channel in 0..4 {
+                                l[channel] += r[channel];
+                            }
+                        }
+                        lhs
+                    },
+                );
+
+            let mut max_shift: f32 = 0.0;
+            for (centroid, sum) in centroids.iter_mut().zip(&sums) {
+                // Empty clusters keep their previous centroid
+                if sum[3] == 0 {
+                    continue;
+                }
+                let count = sum[3] as f64;
+                let new_centroid = [
+                    (sum[0] as f64 / count) as f32,
+                    (sum[1] as f64 / count) as f32,
+                    (sum[2] as f64 / count) as f32,
+                ];
+                max_shift = max_shift.max(Self::points_distance(*centroid, new_centroid).sqrt());
+                *centroid = new_centroid;
+            }
+            if max_shift <= params.convergence_threshold {
+                break;
+            }
+        }
+
+        centroids
+            .iter()
+            .map(|centroid| {
+                Color32::from_rgb(
+                    centroid[0].round() as u8,
+                    centroid[1].round() as u8,
+                    centroid[2].round() as u8,
+                )
+            })
+            .collect()
+    }
+}
+
+impl PaletteBuilder for KMeansColorQuantizer {
+    type Params = KMeansParameters;
+
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette {
+        Palette::new(Self::find_palette(&params, initial_image))
+    }
+}
+
+impl ColorQuantizer for KMeansColorQuantizer {
+    type Params = KMeansParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let palette = Self::build_palette(params, initial_image);
+        PaletteCommon::map_to_palette(initial_image, palette.colors, params.metric)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct WuBox {
+    // Bounds in the histogram, lower bound is exclusive and upper bound is inclusive
+    lower: [usize; 3],
+    upper: [usize; 3],
+    volume: usize,
+}
+
+// Cumulative moments of the color histogram, as described in Xiaolin Wu's "Efficient Statistical Computations for Optimal Color Quantization"
+struct WuMoments {
+    weights: Vec<i64>,
+    r_sums: Vec<i64>,
+    g_sums: Vec<i64>,
+    b_sums: Vec<i64>,
+    squares: Vec<i64>,
+}
+
+impl WuMoments {
+    // Each channel is reduced to 5 bits, additional index 0 makes computing moments of boxes easier
+    const SIDE: usize = 33;
+
+    fn index(coords: [usize; 3]) -> usize {
+        (coords[0] * Self::SIDE + coords[1]) * Self::SIDE + coords[2]
+    }
+
+    fn new(initial_image: &ColorImage) -> WuMoments {
+        let size = Self::SIDE * Self::SIDE * Self::SIDE;
+        let empty = || vec![[0i64; 5]; size];
+        let histogram = initial_image
+            .pixels
+            .par_chunks(4096)
+            .fold(empty, |mut histogram, chunk| {
+                for &pixel in chunk {
+                    if PaletteCommon::is_masked(pixel) {
+                        continue;
+                    }
+                    let (r, g, b) = (pixel.r() as i64, pixel.g() as i64, pixel.b() as i64);
+                    let id = Self::index([
+                        (pixel.r() >> 3) as usize + 1,
+                        (pixel.g() >> 3) as usize + 1,
+                        (pixel.b() >> 3) as usize + 1,
+                    ]);
+                    let entry = &mut histogram[id];
+                    entry[0] += 1;
+                    entry[1] += r;
+                    entry[2] += g;
+                    entry[3] += b;
+                    entry[4] += r * r + g * g + b * b;
+                }
+                histogram
+            })
+            .reduce(empty, |mut lhs, rhs| {
+                for (l, r) in lhs.iter_mut().zip(&rhs) {
+                    for moment in 0..5 {
+                        l[moment] += r[moment];
+                    }
+                }
+                lhs
+            });
+
+        // Turn histogram into cumulative moments, so that moments of any box can be computed in constant time
+        let mut moments = histogram;
+        for r in 1..Self::SIDE {
+            let mut area = vec![[0i64; 5]; Self::SIDE];
+            for g in 1..Self::SIDE {
+                let mut line = [0i64; 5];
+                for (b, area_entry) in area.iter_mut().enumerate().skip(1) {
+                    let id = Self::index([r, g, b]);
+                    let previous_id = Self::index([r - 1, g, b]);
+                    for moment in 0..5 {
+                        line[moment] += moments[id][moment];
+                        area_entry[moment] += line[moment];
+                        moments[id][moment] = moments[previous_id][moment] + area_entry[moment];
+                    }
+                }
+            }
+        }
+
+        let extract = |moment: usize| moments.iter().map(|m| m[moment]).collect();
+        WuMoments {
+            weights: extract(0),
+            r_sums: extract(1),
+            g_sums: extract(2),
+            b_sums: extract(3),
+            squares: extract(4),
+        }
+    }
+
+    // Sum of the moment over the face of the box, which is perpendicular to `channel` and placed at `position`
+    fn top(moment: &[i64], cube: &WuBox, channel: usize, position: usize) -> i64 {
+        let a = (channel + 1) % 3;
+        let b = (channel + 2) % 3;
+        let mut sum = 0;
+        for (a_value, a_sign) in [(cube.upper[a], 1), (cube.lower[a], -1)] {
+            for (b_value, b_sign) in [(cube.upper[b], 1), (cube.lower[b], -1)] {
+                let mut coords = [0; 3];
+                coords[channel] = position;
+                coords[a] = a_value;
+                coords[b] = b_value;
+                sum += a_sign * b_sign * moment[Self::index(coords)];
+            }
+        }
+        sum
+    }
+
+    fn volume(moment: &[i64], cube: &WuBox) -> i64 {
+        Self::top(moment, cube, 0, cube.upper[0]) - Self::top(moment, cube, 0, cube.lower[0])
+    }
+
+    fn variance(&self, cube: &WuBox) -> f64 {
+        let r = Self::volume(&self.r_sums, cube) as f64;
+        let g = Self::volume(&self.g_sums, cube) as f64;
+        let b = Self::volume(&self.b_sums, cube) as f64;
+        let squares = Self::volume(&self.squares, cube) as f64;
+        let weight = Self::volume(&self.weights, cube) as f64;
+        squares - (r * r + g * g + b * b) / weight
+    }
+
+    // Finds cut position along `channel` which maximizes variance reduction
+    // Returns the maximized value and position of the cut (if any is possible)
+    fn maximize(&self, cube: &WuBox, channel: usize, whole: [i64; 4]) -> (f64, Option<usize>) {
+        let moments = [&self.r_sums, &self.g_sums, &self.b_sums, &self.weights];
+        let base = moments.map(|moment| -Self::top(moment, cube, channel, cube.lower[channel]));
+        let mut max = 0.0;
+        let mut cut = None;
+        for position in (cube.lower[channel] + 1)..cube.upper[channel] {
+            let mut half = [0i64; 4];
+            for i in 0..4 {
+                half[i] = base[i] + Self::top(moments[i], cube, channel, position);
+            }
+            if half[3] == 0 {
+                continue;
+            }
+            let other_half: [i64; 4] = std::array::from_fn(|i| whole[i] - half[i]);
+            if other_half[3] == 0 {
+                continue;
+            }
+            let score = |h: [i64; 4]| {
+                let (r, g, b) = (h[0] as f64, h[1] as f64, h[2] as f64);
+                (r * r + g * g + b * b) / h[3] as f64
+            };
+            let value = score(half) + score(other_half);
+            if value > max {
+                max = value;
+                cut = Some(position);
+            }
+        }
+        (max, cut)
+    }
+
+    // Cuts the box into two along channel giving the best variance reduction
+    // Returns `None` if the box can't be cut
+    fn cut(&self, cube: &mut WuBox) -> Option<WuBox> {
+        let whole = [
+            Self::volume(&self.r_sums, cube),
+            Self::volume(&self.g_sums, cube),
+            Self::volume(&self.b_sums, cube),
+            Self::volume(&self.weights, cube),
+        ];
+        let (channel, (_, cut)) = (0..3)
+            .map(|channel| (channel, self.maximize(cube, channel, whole)))
+            .max_by(|(_, (lhs, _)), (_, (rhs, _))| {
+                lhs.partial_cmp(rhs)
+                    .expect("Variances should always be comparable")
+            })
+            .expect("There are always three channels");
+        let cut = cut?;
+
+        let mut other = *cube;
+        cube.upper[channel] = cut;
+        other.lower[channel] = cut;
+        for cube in [&mut *cube, &mut other] {
+            cube.volume = (0..3).map(|i| cube.upper[i] - cube.lower[i]).product();
+        }
+        Some(other)
+    }
+
+    fn average_color(&self, cube: &WuBox) -> Option<Color32> {
+        let weight = Self::volume(&self.weights, cube);
+        if weight == 0 {
+            return None;
+        }
+        let avg =
+            |moment: &[i64]| ((Self::volume(moment, cube) as f64) / (weight as f64)).round() as u8;
+        Some(Color32::from_rgb(
+            avg(&self.r_sums),
+            avg(&self.g_sums),
+            avg(&self.b_sums),
+        ))
+    }
+}
+
+pub struct WuColorQuantizer;
+
+impl WuColorQuantizer {
+    const MAX_COLORS: usize = 256;
+
+    fn find_palette(initial_image: &ColorImage, k: usize) -> Vec<Color32> {
+        let k = k.clamp(1, Self::MAX_COLORS);
+        let moments = WuMoments::new(initial_image);
+
+        let side = WuMoments::SIDE - 1;
+        let mut cubes = vec![WuBox {
+            lower: [0; 3],
+            upper: [side; 3],
+            volume: side * side * side,
+        }];
+        let mut variances = vec![0.0];
+        let mut next = 0;
+        while cubes.len() < k {
+            match moments.cut(&mut cubes[next]) {
+                Some(other) => {
+                    cubes.push(other);
+                    variances.push(0.0);
+                    // Boxes containing single histogram cell can't be cut anymore
+                    for id in [next, cubes.len() - 1] {
+                        variances[id] = if cubes[id].volume > 1 {
+                            moments.variance(&cubes[id])
+                        } else {
+                            0.0
+                        };
+                    }
+                }
+                None => variances[next] = 0.0,
+            }
+            // Next box to cut is the one with the biggest variance
+            let (id, &variance) = variances
+                .iter()
+                .enumerate()
+                .max_by(|(_, lhs), (_, rhs)| {
+                    lhs.partial_cmp(rhs)
+                        .expect("Variances should always be comparable")
+                })
+                .expect("There is always at least one box");
+            if variance <= 0.0 {
+                break;
+            }
+            next = id;
+        }
+
+        let colors: Vec<Color32> = cubes
+            .iter()
+            .filter_map(|cube| moments.average_color(cube))
+            .collect();
+        if colors.is_empty() {
+            vec![Color32::BLACK]
+        } else {
+            colors
+        }
+    }
+}
+
+impl PaletteBuilder for WuColorQuantizer {
+    type Params = WuParameters;
+
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette {
+        Palette::new(Self::find_palette(initial_image, params.k))
+    }
+}
+
+impl ColorQuantizer for WuColorQuantizer {
+    type Params = WuParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let palette = Self::build_palette(params, initial_image);
+        PaletteCommon::map_to_palette(initial_image, palette.colors, params.metric)
+    }
+}
+
+// Self-organizing map from Anthony Dekker's "Kohonen neural networks for optimal colour quantization"
+// Integer arithmetic follows the original NeuQuant implementation
+struct NeuQuantNetwork {
+    // Each neuron is a color with channels scaled by `NETWORK_BIAS_SHIFT`
+    neurons: Vec<[i32; 3]>,
+    bias: Vec<i32>,
+    frequency: Vec<i32>,
+}
+
+impl NeuQuantNetwork {
+    const CYCLES: usize = 100;
+    const NETWORK_BIAS_SHIFT: i32 = 4;
+    const INT_BIAS_SHIFT: i32 = 16;
+    const INT_BIAS: i32 = 1 << Self::INT_BIAS_SHIFT;
+    const GAMMA_SHIFT: i32 = 10;
+    const BETA_SHIFT: i32 = 10;
+    const BETA: i32 = Self::INT_BIAS >> Self::BETA_SHIFT;
+    const BETA_GAMMA: i32 = Self::INT_BIAS << (Self::GAMMA_SHIFT - Self::BETA_SHIFT);
+    const RADIUS_BIAS_SHIFT: i32 = 6;
+    const RADIUS_BIAS: i32 = 1 << Self::RADIUS_BIAS_SHIFT;
+    const RADIUS_DECREASE: i32 = 30;
+    const ALPHA_BIAS_SHIFT: i32 = 10;
+    const INIT_ALPHA: i32 = 1 << Self::ALPHA_BIAS_SHIFT;
+    const RAD_BIAS_SHIFT: i32 = 8;
+    const RAD_BIAS: i32 = 1 << Self::RAD_BIAS_SHIFT;
+    const ALPHA_RAD_BIAS: i32 = 1 << (Self::ALPHA_BIAS_SHIFT + Self::RAD_BIAS_SHIFT);
+    // Steps used for visiting pixels in pseudo-random order
+    const PRIMES: [usize; 4] = [499, 491, 487, 503];
+
+    fn new(size: usize) -> NeuQuantNetwork {
+        let neurons = (0..size)
+            .map(|i| {
+                let value = ((i as i32) << (Self::NETWORK_BIAS_SHIFT + 8)) / size as i32;
+                [value; 3]
+            })
+            .collect();
+        NeuQuantNetwork {
+            neurons,
+            bias: vec![0; size],
+            frequency: vec![Self::INT_BIAS / size as i32; size],
+        }
+    }
+
+    // Finds the closest neuron, updates frequencies and biases, and returns the best neuron taking bias into account
+    fn contest(&mut self, color: [i32; 3]) -> usize {
+        let mut best_distance = i32::MAX;
+        let mut best_bias_distance = i32::MAX;
+        let mut best_position = 0;
+        let mut best_bias_position = 0;
+        for i in 0..self.neurons.len() {
+            let neuron = self.neurons[i];
+            let distance = (0..3).map(|c| (neuron[c] - color[c]).abs()).sum::<i32>();
+            if distance < best_distance {
+                best_distance = distance;
+                best_position = i;
+            }
+            let bias_distance =
+                distance - (self.bias[i] >> (Self::INT_BIAS_SHIFT - Self::NETWORK_BIAS_SHIFT));
+            if bias_distance < best_bias_distance {
+                best_bias_distance = bias_distance;
+                best_bias_position = i;
+            }
+            let beta_frequency = self.frequency[i] >> Self::BETA_SHIFT;
+            self.frequency[i] -= beta_frequency;
+            self.bias[i] += beta_frequency << Self::GAMMA_SHIFT;
+        }
+        self.frequency[best_position] += Self::BETA;
+        self.bias[best_position] -= Self::BETA_GAMMA;
+        best_bias_position
+    }
+
+    fn alter_single(&mut self, alpha: i32, i: usize, color: [i32; 3]) {
+        for (channel, &value) in self.neurons[i].iter_mut().zip(&color) {
+            *channel -= (alpha * (*channel - value)) / Self::INIT_ALPHA;
+        }
+    }
+
+    fn alter_neighbours(&mut self, radius: usize, radius_power: &[i32], i: usize, color: [i32; 3]) {
+        // Only neurons closer than `radius` are affected
+        let low = i.saturating_sub(radius - 1);
+        let high = (i + radius - 1).min(self.neurons.len() - 1);
+        for j in low..=high {
+            if j == i {
+                continue;
+            }
+            let power = radius_power[i.abs_diff(j)];
+            for (channel, &value) in self.neurons[j].iter_mut().zip(&color) {
+                *channel -= (power * (*channel - value)) / Self::ALPHA_RAD_BIAS;
+            }
+        }
+    }
+
+    fn radius_power(alpha: i32, radius: usize) -> Vec<i32> {
+        let radius = radius as i32;
+        let radius_sq = (radius * radius).max(1);
+        (0..radius.max(1))
+            .map(|i| alpha * (((radius_sq - i * i) * Self::RAD_BIAS) / radius_sq))
+            .collect()
+    }
+
+    fn learn(&mut self, pixels: &[Color32], sampling_factor: usize) {
+        let pixels_count = pixels.len();
+        let initial_radius = (self.neurons.len() as i32 >> 3) * Self::RADIUS_BIAS;
+        // Small images are not sampled at all
+        let (sampling_factor, step) = if pixels_count < Self::PRIMES[3] {
+            (1, 1)
+        } else {
+            let step = *Self::PRIMES
+                .iter()
+                .find(|&&prime| pixels_count % prime != 0)
+                .unwrap_or(&Self::PRIMES[3]);
+            (sampling_factor, step)
+        };
+        let alpha_decrease = 30 + (sampling_factor as i32 - 1) / 3;
+        let samples = pixels_count / sampling_factor;
+        let delta = (samples / Self::CYCLES).max(1);
+
+        let mut alpha = Self::INIT_ALPHA;
+        let mut radius = initial_radius;
+        let to_neighbours_radius = |radius: i32| match radius >> Self::RADIUS_BIAS_SHIFT {
+            r if r <= 1 => 0,
+            r => r as usize,
+        };
+        let mut neighbours_radius = to_neighbours_radius(radius);
+        let mut radius_power = Self::radius_power(alpha, neighbours_radius);
+
+        let mut position = 0;
+        for i in 1..=samples {
+            let pixel = pixels[position];
+            let color = [pixel.r(), pixel.g(), pixel.b()]
+                .map(|channel| (channel as i32) << Self::NETWORK_BIAS_SHIFT);
+            let j = self.contest(color);
+            self.alter_single(alpha, j, color);
+            if neighbours_radius != 0 {
+                self.alter_neighbours(neighbours_radius, &radius_power, j, color);
+            }
+
+            position = (position + step) % pixels_count;
+            if i % delta == 0 {
+                alpha -= alpha / alpha_decrease;
+                radius -= radius / Self::RADIUS_DECREASE;
+                neighbours_radius = to_neighbours_radius(radius);
+                radius_power = Self::radius_power(alpha, neighbours_radius);
+            }
+        }
+    }
+
+    fn palette(&self) -> Vec<Color32> {
+        self.neurons
+            .iter()
+            .map(|neuron| {
+                let [r, g, b] = neuron.map(|channel| {
+                    let rounded = (channel + (1 << (Self::NETWORK_BIAS_SHIFT - 1)))
+                        >> Self::NETWORK_BIAS_SHIFT;
+                    rounded.clamp(0, 255) as u8
+                });
+                Color32::from_rgb(r, g, b)
+            })
+            .collect()
+    }
+}
+
+pub struct NeuQuantColorQuantizer;
+
+impl NeuQuantColorQuantizer {
+    const MAX_COLORS: usize = 256;
+    const MAX_SAMPLING_FACTOR: u8 = 30;
+
+    fn find_palette(params: &NeuQuantParameters, initial_image: &ColorImage) -> Vec<Color32> {
+        let pixels: Vec<Color32> = initial_image
+            .pixels
+            .iter()
+            .copied()
+            .filter(|&pixel| !PaletteCommon::is_masked(pixel))
+            .collect();
+        if pixels.is_empty() {
+            return vec![Color32::BLACK];
+        }
+        // Network needs at least two neurons, while the single closest color to all pixels is their average
+        if params.k <= 1 {
+            let sums = pixels.iter().fold([0u64; 3], |[r, g, b], pixel| {
+                [
+                    r + pixel.r() as u64,
+                    g + pixel.g() as u64,
+                    b + pixel.b() as u64,
+                ]
+            });
+            let [r, g, b] = sums.map(|sum| (sum as f64 / pixels.len() as f64).round() as u8);
+            return vec![Color32::from_rgb(r, g, b)];
+        }
+        let k = params.k.min(Self::MAX_COLORS);
+        let sampling_factor = params.sampling_factor.clamp(1, Self::MAX_SAMPLING_FACTOR);
+        let mut network = NeuQuantNetwork::new(k);
+        network.learn(&pixels, sampling_factor as usize);
+        network.palette()
+    }
+}
+
+impl PaletteBuilder for NeuQuantColorQuantizer {
+    type Params = NeuQuantParameters;
+
+    fn build_palette(params: Self::Params, initial_image: &ColorImage) -> Palette {
+        Palette::new(Self::find_palette(&params, initial_image))
+    }
+}
+
+impl ColorQuantizer for NeuQuantColorQuantizer {
+    type Params = NeuQuantParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let palette = Self::build_palette(params, initial_image);
+        PaletteCommon::map_to_palette(initial_image, palette.colors, params.metric)
+    }
+}
+
+pub struct FixedPaletteColorQuantizer;
+
+impl PaletteBuilder for FixedPaletteColorQuantizer {
+    type Params = FixedPaletteParameters;
+
+    fn build_palette(params: Self::Params, _initial_image: &ColorImage) -> Palette {
+        Palette::fixed(&params.palette)
+    }
+}
+
+impl ColorQuantizer for FixedPaletteColorQuantizer {
+    type Params = FixedPaletteMappingParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let target = DitheringTarget::Palette(PaletteSource::Fixed(params.palette.clone()));
+        match params.dithering {
+            FixedPaletteDithering::None => {
+                let metric = params.palette.metric;
+                let palette = Self::build_palette(params.palette, initial_image);
+                PaletteCommon::map_to_palette(initial_image, palette.colors, metric)
+            }
+            FixedPaletteDithering::ErrorDiffusion {
+                kernel,
+                serpentine,
+                error_clamp,
+            } => {
+                let params = ErrorDiffusionParameters {
+                    kernel,
+                    serpentine,
+                    error_clamp,
+                    target,
+                    ..Default::default()
+                };
+                ErrorDiffusionDitheringColorQuantizer::generate_output_image(params, initial_image)
+            }
+            FixedPaletteDithering::Ordered { matrix_size } => {
+                let params = OrderedDitheringParameters {
+                    target,
+                    matrix_size,
+                    ..Default::default()
+                };
+                OrderedDitheringRelativeColorQuantizer::generate_output_image(params, initial_image)
+            }
+        }
+    }
+}
+
+pub struct ErrorDiffusionDitheringColorQuantizer;
+
+impl ErrorDiffusionDitheringColorQuantizer {
+    fn add_error(
+        buffer: &mut [[f32; 3]],
+        row: usize,
+        col: isize,
+        size: [usize; 2],
+        error: f32,
+        diff: [f32; 3],
+    ) {
+        if col < 0 || col as usize >= size[0] || row >= size[1] {
+            return;
+        }
+        let id = row * size[0] + col as usize;
+        for (value, channel_diff) in buffer[id].iter_mut().zip(diff) {
+            *value += error * channel_diff;
+        }
+    }
+
+    // Limits the error accumulated in the value to `limit` in each channel
+    fn clamp_error(value: [f32; 3], original: [f32; 3], limit: u8) -> [f32; 3] {
+        let limit = limit as f32;
+        std::array::from_fn(|c| original[c] + (value[c] - original[c]).clamp(-limit, limit))
+    }
+
+    // Returns kernel entries as (column offset, row offset, weight) and divisor of the weights
+    fn kernel_entries(kernel: ErrorDiffusionKernel) -> (&'static [(isize, usize, f32)], f32) {
+        match kernel {
+            ErrorDiffusionKernel::FloydSteinberg => {
+                (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0)
+            }
+            ErrorDiffusionKernel::FalseFloydSteinberg => {
+                (&[(1, 0, 3.0), (0, 1, 3.0), (1, 1, 2.0)], 8.0)
+            }
+            ErrorDiffusionKernel::JarvisJudiceNinke => (
+                &[
+                    (1, 0, 7.0),
+                    (2, 0, 5.0),
+                    (-2, 1, 3.0),
+                    (-1, 1, 5.0),
+                    (0, 1, 7.0),
+                    (1, 1, 5.0),
+                    (2, 1, 3.0),
+                    (-2, 2, 1.0),
+                    (-1, 2, 3.0),
+                    (0, 2, 5.0),
+                    (1, 2, 3.0),
+                    (2, 2, 1.0),
+                ],
+                48.0,
+            ),
+            ErrorDiffusionKernel::Stucki => (
+                &[
+                    (1, 0, 8.0),
+                    (2, 0, 4.0),
+                    (-2, 1, 2.0),
+                    (-1, 1, 4.0),
+                    (0, 1, 8.0),
+                    (1, 1, 4.0),
+                    (2, 1, 2.0),
+                    (-2, 2, 1.0),
+                    (-1, 2, 2.0),
+                    (0, 2, 4.0),
+                    (1, 2, 2.0),
+                    (2, 2, 1.0),
+                ],
+                42.0,
+            ),
+            ErrorDiffusionKernel::Burkes => (
+                &[
+                    (1, 0, 8.0),
+                    (2, 0, 4.0),
+                    (-2, 1, 2.0),
+                    (-1, 1, 4.0),
+                    (0, 1, 8.0),
+                    (1, 1, 4.0),
+                    (2, 1, 2.0),
+                ],
+                32.0,
+            ),
+            ErrorDiffusionKernel::Sierra => (
+                &[
+                    (1, 0, 5.0),
+                    (2, 0, 3.0),
+                    (-2, 1, 2.0),
+                    (-1, 1, 4.0),
+                    (0, 1, 5.0),
+                    (1, 1, 4.0),
+                    (2, 1, 2.0),
+                    (-1, 2, 2.0),
+                    (0, 2, 3.0),
+                    (1, 2, 2.0),
+                ],
+                32.0,
+            ),
+            ErrorDiffusionKernel::TwoRowSierra => (
+                &[
+                    (1, 0, 4.0),
+                    (2, 0, 3.0),
+                    (-2, 1, 1.0),
+                    (-1, 1, 2.0),
+                    (0, 1, 3.0),
+                    (1, 1, 2.0),
+                    (2, 1, 1.0),
+                ],
+                16.0,
+            ),
+            ErrorDiffusionKernel::SierraLite => (&[(1, 0, 2.0), (-1, 1, 1.0), (0, 1, 1.0)], 4.0),
+            // Atkinson kernel intentionally diffuses only 3/4 of the error
+            ErrorDiffusionKernel::Atkinson => (
+                &[
+                    (1, 0, 1.0),
+                    (2, 0, 1.0),
+                    (-1, 1, 1.0),
+                    (0, 1, 1.0),
+                    (1, 1, 1.0),
+                    (0, 2, 1.0),
+                ],
+                8.0,
+            ),
+        }
+    }
+}
+
+impl ColorQuantizer for ErrorDiffusionDitheringColorQuantizer {
+    type Params = ErrorDiffusionParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let colors = DitheringColors::new(params.dithering, params.target, initial_image);
+
+        let (kernel, divisor) = Self::kernel_entries(params.kernel);
+
+        let size = initial_image.size;
+
+        // Error is accumulated in floats, so that it is neither truncated nor clamped between pixels
+        let mut buffer: Vec<[f32; 3]> = initial_image
+            .pixels
+            .iter()
+            .map(|&p| colors.working_value(p))
+            .collect();
+        let mut output_pixels = vec![Color32::BLACK; buffer.len()];
+        for row in 0..size[1] {
+            // In serpentine mode every other row is scanned right to left, with mirrored kernel
+            let reversed = params.serpentine && row % 2 == 1;
+            let direction = if reversed { -1 } else { 1 };
+            for step in 0..size[0] {
+                let column = if reversed { size[0] - 1 - step } else { step };
+                let i = row * size[0] + column;
+                let value = match params.error_clamp {
+                    Some(limit) => Self::clamp_error(
+                        buffer[i],
+                        colors.working_value(initial_image.pixels[i]),
+                        limit,
+                    ),
+                    None => buffer[i],
+                };
+                // Value is clamped only for choosing the color, so the error is exact
+                let color = colors.find_closest_color(value);
+                output_pixels[i] = color;
+                let color_value = colors.working_value(color);
+                let diff = std::array::from_fn(|c| value[c] - color_value[c]);
+
+                for &(column_offset, row_offset, weight) in kernel {
+                    Self::add_error(
+                        &mut buffer,
+                        row + row_offset,
+                        column as isize + column_offset * direction,
+                        size,
+                        weight / divisor,
+                        diff,
+                    );
+                }
+            }
+        }
+
+        ColorImage::from_rgba_unmultiplied(
+            size,
+            output_pixels
+                .iter()
+                .flat_map(|&p| p.to_array())
+                .collect::<Vec<_>>()
+                .as_slice(),
+        )
+    }
+}
+
+pub struct RiemersmaDitheringColorQuantizer;
+
+impl RiemersmaDitheringColorQuantizer {
+    // Converts distance along the Hilbert curve filling the `side` x `side` square into (x, y)
+    // Curve covers the smallest power of two square containing the image, points outside are skipped
+    fn hilbert_walk(size: [usize; 2]) -> impl Iterator<Item = (usize, usize)> {
+        let side = size[0].max(size[1]).next_power_of_two();
+        (0..side * side)
+            .map(move |distance| Self::hilbert_point(side, distance))
+            .filter(move |&(x, y)| x < size[0] && y < size[1])
+    }
+
+    fn hilbert_point(side: usize, distance: usize) -> (usize, usize) {
+        let (mut x, mut y) = (0, 0);
+        let mut t = distance;
+        let mut s = 1;
+        while s < side {
+            let rx = 1 & (t / 2);
+            let ry = 1 & (t ^ rx);
+            if ry == 0 {
+                if rx == 1 {
+                    x = s - 1 - x;
+                    y = s - 1 - y;
+                }
+                std::mem::swap(&mut x, &mut y);
+            }
+            x += s * rx;
+            y += s * ry;
+            t /= 4;
+            s *= 2;
+        }
+        (x, y)
+    }
+
+    // Weights of errors in the history queue, from the oldest to the newest one,
+    // they decay exponentially so that the oldest one is `ratio` times the newest one
+    fn history_weights(queue_length: usize, ratio: f32) -> Vec<f32> {
+        if queue_length == 1 {
+            return vec![1.0];
+        }
+        (0..queue_length)
+            .map(|i| ratio.powf((queue_length - 1 - i) as f32 / (queue_length - 1) as f32))
+            .collect()
+    }
+}
+
+impl ColorQuantizer for RiemersmaDitheringColorQuantizer {
+    type Params = RiemersmaParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let colors = DitheringColors::new(params.dithering, params.target, initial_image);
+
+        let queue_length = (params.queue_length as usize).max(1);
+        let weights = Self::history_weights(queue_length, params.ratio);
+        let mut history = VecDeque::from(vec![[0.0f32; 3]; queue_length]);
+
+        let size = initial_image.size;
+        let mut output_pixels = vec![Color32::BLACK; initial_image.pixels.len()];
+        for (x, y) in Self::hilbert_walk(size) {
+            let i = y * size[0] + x;
+            let pixel = initial_image.pixels[i];
+            let pixel_value = colors.working_value(pixel);
+            let mut value = pixel_value;
+            for (error, weight) in history.iter().zip(&weights) {
+                for channel in 0..3 {
+                    value[channel] += error[channel] * weight;
+                }
+            }
+            let color = colors.find_closest_color(value);
+            output_pixels[i] = color;
+            history.pop_front();
+            let color_value = colors.working_value(color);
+            history.push_back(std::array::from_fn(|c| pixel_value[c] - color_value[c]));
+        }
+
+        ColorImage::from_rgba_unmultiplied(
+            size,
+            output_pixels
+                .iter()
+                .flat_map(|&p| p.to_array())
+                .collect::<Vec<_>>()
+                .as_slice(),
+        )
+    }
+}
+
+trait OrderedDitheringCommon {
+    // Returns position in the threshold matrix used for the pixel at (x, y),
+    // `seed` is different for every channel and may be used to pick random positions
+    fn matrix_position(x: usize, y: usize, n: usize, seed: u64) -> (usize, usize);
+
+    // Returns threshold of the pixel at (x, y) in the matrix tiled over the image,
+    // as the value from the matrix together with the number of its values
+    fn matrix_threshold(matrix: &[u32], x: usize, y: usize, n: usize, seed: u64) -> (usize, usize) {
+        let (i, j) = Self::matrix_position(x, y, n, seed);
+        (matrix[i * n + j] as usize, n * n)
+    }
+
+    fn get_color(
+        value: u8,
+        levels: &[u8],
+        matrix: &[u32],
+        x: usize,
+        y: usize,
+        n: usize,
+        seed: u64,
+    ) -> u8 {
+        Self::get_level(value, levels, Self::matrix_threshold(matrix, x, y, n, seed))
+    }
+
+    // Threshold is a value from 0 to `count - 1`, the value is moved to the next level
+    // when it is further from the previous level than the threshold
+    fn get_level(value: u8, levels: &[u8], (threshold, count): (usize, usize)) -> u8 {
+        let scaled_value = value as usize * (levels.len() - 1);
+        let col = scaled_value / 255;
+        let re = scaled_value % 255;
+        let final_col = if re > (threshold * 255 / count) {
+            col + 1
+        } else {
+            col
+        };
+        levels[final_col]
+    }
+
+    const POSSIBLE_N: [usize; 7] = [2, 3, 4, 6, 8, 12, 16];
+
+    fn find_n(k: u8) -> usize {
+        *Self::POSSIBLE_N
+            .iter()
+            .find(|&&n| n * n * (k.max(2) as usize - 1) >= 256)
+            .unwrap_or(Self::POSSIBLE_N.last().unwrap())
+    }
+
+    // Matrix is stored row by row, `n` has to be either power of two or 3 times power of two
+    fn generate_matrix(n: usize) -> Vec<u32> {
+        if n == 2 {
+            return vec![0, 2, 3, 1];
+        }
+        if n == 3 {
+            return vec![6, 8, 4, 1, 0, 3, 5, 2, 7];
+        }
+
+        let mut matrix = vec![0; n * n];
+        let half_matrix = Self::generate_matrix(n / 2);
+        let half_n = n / 2;
+        for i in 0..half_n {
+            for j in 0..half_n {
+                let value = 4 * half_matrix[i * half_n + j];
+                matrix[i * n + j] = value;
+                matrix[i * n + j + half_n] = value + 2;
+                matrix[(i + half_n) * n + j] = value + 3;
+                matrix[(i + half_n) * n + j + half_n] = value + 1;
+            }
+        }
+        matrix
+    }
+
+    // Returns size and threshold matrix for given number of levels in a channel,
+    // in auto mode size is chosen so that the matrix has enough distinct thresholds
+    fn bayer_matrix(k: u8, size: BayerMatrixSize) -> (usize, Cow<'static, [u32]>) {
+        let n = size.side().unwrap_or_else(|| Self::find_n(k));
+        (n, Cow::Owned(Self::generate_matrix(n)))
+    }
+
+    fn ordered_dithering_output_image(
+        params: OrderedDitheringParameters,
+        initial_image: &ColorImage,
+    ) -> ColorImage {
+        Self::ordered_dithering_output_image_with_matrix(
+            params.dithering,
+            params.target,
+            0,
+            initial_image,
+            |k| Self::bayer_matrix(k, params.matrix_size),
+        )
+    }
+
+    // `threshold_matrix` returns size and threshold matrix (with values from 0 to n^2 - 1, stored row by row)
+    // which should be used for given number of levels in a channel, precomputed matrices are borrowed
+    fn ordered_dithering_output_image_with_matrix(
+        dithering: DitheringParameters,
+        target: DitheringTarget,
+        seed: u64,
+        initial_image: &ColorImage,
+        threshold_matrix: impl Fn(u8) -> (usize, Cow<'static, [u32]>),
+    ) -> ColorImage {
+        match target {
+            DitheringTarget::Levels => {
+                let matrices = [dithering.k_r, dithering.k_g, dithering.k_b].map(threshold_matrix);
+                Self::ordered_dithering_levels_output_image(
+                    dithering,
+                    initial_image,
+                    |channel, x, y| {
+                        let (n, matrix) = &matrices[channel];
+                        let seed = seed.wrapping_add(channel as u64);
+                        Self::matrix_threshold(matrix, x, y, *n, seed)
+                    },
+                )
+            }
+            DitheringTarget::Palette(source) => {
+                let palette = Palette::build(&source, initial_image);
+                let matcher = PaletteMatcher::new(palette.colors, source.metric());
+                let (n, matrix) = threshold_matrix(Self::palette_levels_per_channel(&matcher));
+                Self::ordered_dithering_palette_output_image(&matcher, initial_image, |x, y| {
+                    Self::matrix_threshold(&matrix, x, y, n, seed)
+                })
+            }
+        }
+    }
+
+    // `thresholds` returns threshold of the pixel at (x, y) in given channel (0, 1 and 2 for red, green and blue),
+    // as a value from 0 to `count - 1` together with the `count`
+    fn ordered_dithering_levels_output_image(
+        params: DitheringParameters,
+        initial_image: &ColorImage,
+        thresholds: impl Fn(usize, usize, usize) -> (usize, usize) + Sync,
+    ) -> ColorImage {
+        let r_levels = DitheringCommon::generate_color_levels(params.k_r);
+        let g_levels = DitheringCommon::generate_color_levels(params.k_g);
+        let b_levels = DitheringCommon::generate_color_levels(params.k_b);
+
+        let prepare = |value: u8, levels: &[u8]| {
+            if params.linear_light {
+                DitheringCommon::linear_light_value(value, levels)
+            } else {
+                value
+            }
+        };
+
+        const CHUNK_SIZE: usize = 512;
+
+        let size = initial_image.size;
+
+        let output_pixesl: Vec<_> = initial_image
+            .pixels
+            .par_chunks(CHUNK_SIZE)
+            .enumerate()
+            .flat_map(|(chunk_id, chunk)| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(pixel_id, &pixel)| {
+                        let id = chunk_id * CHUNK_SIZE + pixel_id;
+                        let x = id / size[0];
+                        let y = id - x * size[0];
+                        let new_r = Self::get_level(
+                            prepare(pixel.r(), &r_levels),
+                            &r_levels,
+                            thresholds(0, x, y),
+                        );
+                        let new_g = Self::get_level(
+                            prepare(pixel.g(), &g_levels),
+                            &g_levels,
+                            thresholds(1, x, y),
+                        );
+                        let new_b = Self::get_level(
+                            prepare(pixel.b(), &b_levels),
+                            &b_levels,
+                            thresholds(2, x, y),
+                        );
+                        let new_pixel = Color32::from_rgb(new_r, new_g, new_b);
+                        new_pixel.to_array()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        ColorImage::from_rgba_unmultiplied(size, output_pixesl.as_slice())
+    }
+
+    // Palette is treated as if it was a uniform grid with the same number of colors
+    fn palette_levels_per_channel(matcher: &PaletteMatcher) -> u8 {
+        (matcher.colors.len() as f32)
+            .cbrt()
+            .round()
+            .clamp(2.0, 255.0) as u8
+    }
+
+    // Each pixel is moved by an offset taken from its threshold (returned by `threshold` like in levels dithering),
+    // and then mapped to the closest palette color
+    fn ordered_dithering_palette_output_image(
+        matcher: &PaletteMatcher,
+        initial_image: &ColorImage,
+        threshold: impl Fn(usize, usize) -> (usize, usize) + Sync,
+    ) -> ColorImage {
+        let spread = 255.0 / (Self::palette_levels_per_channel(matcher) - 1) as f32;
+
+        const CHUNK_SIZE: usize = 512;
+
+        let size = initial_image.size;
+
+        let output_pixels: Vec<_> = initial_image
+            .pixels
+            .par_chunks(CHUNK_SIZE)
+            .enumerate()
+            .flat_map(|(chunk_id, chunk)| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(pixel_id, &pixel)| {
+                        let id = chunk_id * CHUNK_SIZE + pixel_id;
+                        let x = id / size[0];
+                        let y = id - x * size[0];
+                        let (value, count) = threshold(x, y);
+                        let offset = ((value as f32 + 0.5) / count as f32 - 0.5) * spread;
+                        let shifted = [pixel.r(), pixel.g(), pixel.b()]
+                            .map(|channel| (channel as f32 + offset).clamp(0.0, 255.0) as u8);
+                        let shifted = Color32::from_rgb(shifted[0], shifted[1], shifted[2]);
+                        matcher.find_closest_color(shifted).to_array()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        ColorImage::from_rgba_unmultiplied(size, output_pixels.as_slice())
+    }
+}
+
+pub struct OrderedDitheringRelativeColorQuantizer;
+
+impl OrderedDitheringCommon for OrderedDitheringRelativeColorQuantizer {
+    fn matrix_position(x: usize, y: usize, n: usize, _seed: u64) -> (usize, usize) {
+        (x % n, y % n)
+    }
+}
+
+impl ColorQuantizer for OrderedDitheringRelativeColorQuantizer {
+    type Params = OrderedDitheringParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        Self::ordered_dithering_output_image(params, initial_image)
+    }
+}
+
+pub struct OrderedDitheringRandomColorQuantizer;
+
+impl OrderedDitheringRandomColorQuantizer {
+    // SplitMix64 finalizer, mixes all bits of the input
+    fn mix(mut value: u64) -> u64 {
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+        value ^ (value >> 31)
+    }
+
+    // Random value depending only on the seed and pixel coordinates, so the result
+    // doesn't depend on the order in which pixels are processed
+    fn pixel_random(seed: u64, x: usize, y: usize) -> u64 {
+        let hash = Self::mix(seed.wrapping_add(0x9e3779b97f4a7c15));
+        let hash = Self::mix(hash ^ x as u64);
+        Self::mix(hash ^ y as u64)
+    }
+}
+
+impl OrderedDitheringCommon for OrderedDitheringRandomColorQuantizer {
+    fn matrix_position(x: usize, y: usize, n: usize, seed: u64) -> (usize, usize) {
+        let random = Self::pixel_random(seed, x, y);
+        let i = ((random >> 32) % n as u64) as usize;
+        let j = ((random & 0xffffffff) % n as u64) as usize;
+        (i, j)
+    }
+}
+
+impl ColorQuantizer for OrderedDitheringRandomColorQuantizer {
+    type Params = RandomOrderedDitheringParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        Self::ordered_dithering_output_image_with_matrix(
+            params.dithering,
+            params.target,
+            params.seed,
+            initial_image,
+            |k| Self::bayer_matrix(k, params.matrix_size),
+        )
+    }
+}
+
+pub struct OrderedDitheringBlueNoiseColorQuantizer;
+
+impl OrderedDitheringBlueNoiseColorQuantizer {
+    const SIGMA: f32 = 1.5;
+    // Gaussian filter is truncated to this radius, further values are negligible
+    const FILTER_RADIUS: isize = 6;
+    // Fraction of pixels set in the initial binary pattern
+    const INITIAL_DENSITY: f32 = 0.1;
+    const SEED: u64 = 0;
+
+    // Generating threshold maps is expensive, so they are computed once for each size
+    // Every size has its own cell, so generating one of them doesn't block requests for the others
+    fn threshold_matrix(size: BlueNoiseSize) -> &'static [u32] {
+        static MATRICES: [OnceLock<Vec<u32>>; 4] = [const { OnceLock::new() }; 4];
+        let id = match size {
+            BlueNoiseSize::Size16 => 0,
+            BlueNoiseSize::Size32 => 1,
+            BlueNoiseSize::Size64 => 2,
+            BlueNoiseSize::Size128 => 3,
+        };
+        MATRICES[id].get_or_init(|| Self::generate_void_and_cluster_matrix(size.side()))
+    }
+
+    // Adds (or subtracts) gaussian energy of the pixel at `id` to its neighbourhood, wrapping around edges
+    fn update_energy(energy: &mut [f32], filter: &[f32], n: usize, id: usize, sign: f32) {
+        let (row, col) = ((id / n) as isize, (id % n) as isize);
+        let side = 2 * Self::FILTER_RADIUS + 1;
+        for dy in -Self::FILTER_RADIUS..=Self::FILTER_RADIUS {
+            for dx in -Self::FILTER_RADIUS..=Self::FILTER_RADIUS {
+                let y = (row + dy).rem_euclid(n as isize) as usize;
+                let x = (col + dx).rem_euclid(n as isize) as usize;
+                let weight =
+                    filter[((dy + Self::FILTER_RADIUS) * side + dx + Self::FILTER_RADIUS) as usize];
+                energy[y * n + x] += sign * weight;
+            }
+        }
+    }
+
+    // Minority pixel with the highest energy
+    fn find_tightest_cluster(pattern: &[bool], energy: &[f32]) -> usize {
+        (0..pattern.len())
+            .filter(|&id| pattern[id])
+            .max_by(|&lhs, &rhs| energy[lhs].total_cmp(&energy[rhs]).then(rhs.cmp(&lhs)))
+            .expect("Pattern should contain at least one minority pixel")
+    }
+
+    // Majority pixel with the lowest energy
+    fn find_largest_void(pattern: &[bool], energy: &[f32]) -> usize {
+        (0..pattern.len())
+            .filter(|&id| !pattern[id])
+            .min_by(|&lhs, &rhs| energy[lhs].total_cmp(&energy[rhs]).then(lhs.cmp(&rhs)))
+            .expect("Pattern should contain at least one majority pixel")
+    }
+
+    // Robert Ulichney's void-and-cluster method
+    fn generate_void_and_cluster_matrix(n: usize) -> Vec<u32> {
+        let pixels = n * n;
+        let side = 2 * Self::FILTER_RADIUS + 1;
+        let filter: Vec<f32> = (0..side * side)
+            .map(|id| {
+                let dy = (id / side - Self::FILTER_RADIUS) as f32;
+                let dx = (id % side - Self::FILTER_RADIUS) as f32;
+                (-(dx * dx + dy * dy) / (2.0 * Self::SIGMA * Self::SIGMA)).exp()
+            })
+            .collect();
+
+        // Random initial pattern
+        let mut rng = StdRng::seed_from_u64(Self::SEED);
+        let ones = ((pixels as f32 * Self::INITIAL_DENSITY) as usize).clamp(1, pixels / 2);
+        let mut pattern = vec![false; pixels];
+        let mut energy = vec![0.0; pixels];
+        for id in rand::seq::index::sample(&mut rng, pixels, ones) {
+            pattern[id] = true;
+            Self::update_energy(&mut energy, &filter, n, id, 1.0);
+        }
+
+        // Move pixels from the tightest clusters to the largest voids until pattern is stable
+        loop {
+            let cluster = Self::find_tightest_cluster(&pattern, &energy);
+            pattern[cluster] = false;
+            Self::update_energy(&mut energy, &filter, n, cluster, -1.0);
+            let void = Self::find_largest_void(&pattern, &energy);
+            pattern[void] = true;
+            Self::update_energy(&mut energy, &filter, n, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0u32; pixels];
+
+        // Pixels of the initial pattern are ranked by removing the tightest clusters
+        let mut phase_pattern = pattern.clone();
+        let mut phase_energy = energy.clone();
+        for rank in (0..ones).rev() {
+            let cluster = Self::find_tightest_cluster(&phase_pattern, &phase_energy);
+            phase_pattern[cluster] = false;
+            Self::update_energy(&mut phase_energy, &filter, n, cluster, -1.0);
+            ranks[cluster] = rank as u32;
+        }
+
+        // Pixels up to the half are ranked by filling the largest voids
+        for rank in ones..pixels / 2 {
+            let void = Self::find_largest_void(&pattern, &energy);
+            pattern[void] = true;
+            Self::update_energy(&mut energy, &filter, n, void, 1.0);
+            ranks[void] = rank as u32;
+        }
+
+        // Once ones are the majority, zeros become the minority pixels, so the remaining ones
+        // are ranked by removing the tightest clusters of zeros (with energy of zeros computed anew)
+        let mut zeros: Vec<bool> = pattern.iter().map(|&one| !one).collect();
+        let mut zeros_energy = vec![0.0; pixels];
+        for id in (0..pixels).filter(|&id| zeros[id]) {
+            Self::update_energy(&mut zeros_energy, &filter, n, id, 1.0);
+        }
+        for rank in pixels / 2..pixels {
+            let cluster = Self::find_tightest_cluster(&zeros, &zeros_energy);
+            zeros[cluster] = false;
+            Self::update_energy(&mut zeros_energy, &filter, n, cluster, -1.0);
+            ranks[cluster] = rank as u32;
+        }
+
+        ranks
+    }
+}
+
+impl OrderedDitheringCommon for OrderedDitheringBlueNoiseColorQuantizer {
+    fn matrix_position(x: usize, y: usize, n: usize, _seed: u64) -> (usize, usize) {
+        (x % n, y % n)
+    }
+}
+
+impl ColorQuantizer for OrderedDitheringBlueNoiseColorQuantizer {
+    type Params = BlueNoiseDitheringParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let n = params.size.side();
+        let matrix = Self::threshold_matrix(params.size);
+        Self::ordered_dithering_output_image_with_matrix(
+            params.dithering,
+            params.target,
+            0,
+            initial_image,
+            |_| (n, Cow::Borrowed(matrix)),
+        )
+    }
+}
+
+pub struct PatternDitheringColorQuantizer;
+
+impl PatternDitheringColorQuantizer {
+    // Fraction of accumulated error added to the pixel when choosing next candidate in Knoll's algorithm
+    const KNOLL_ERROR_MULTIPLIER: f32 = 0.5;
+    // Building a plan compares candidates with every palette color, so bigger palettes are reduced first
+    const MAX_PALETTE_COLORS: usize = 256;
+
+    // Median cut splits the palette into evenly spread groups, each of them is replaced with
+    // the palette color closest to its average, so that the result is still a subset of the palette
+    fn limit_palette(matcher: PaletteMatcher) -> PaletteMatcher {
+        if matcher.colors.len() <= Self::MAX_PALETTE_COLORS {
+            return matcher;
+        }
+        let palette_image = ColorImage {
+            size: [matcher.colors.len(), 1],
+            pixels: matcher.colors.clone(),
+        };
+        let mut colors: Vec<Color32> =
+            MedianCutColorQuantizer::find_palette(&palette_image, Self::MAX_PALETTE_COLORS)
+                .into_iter()
+                .map(|color| matcher.find_closest_color(color))
+                .collect();
+        colors.sort_unstable_by_key(|color| color.to_array());
+        colors.dedup();
+        PaletteMatcher::new(colors, matcher.metric)
+    }
+
+    fn luminance(color: Color32) -> u32 {
+        color.r() as u32 * 299 + color.g() as u32 * 587 + color.b() as u32 * 114
+    }
+
+    // Knoll's (Adobe) pattern dithering: each candidate is the palette color closest
+    // to the pixel corrected by the error of candidates chosen so far
+    fn knoll_plan(pixel: Color32, matcher: &PaletteMatcher, plan_size: usize) -> Vec<Color32> {
+        let value = [pixel.r() as f32, pixel.g() as f32, pixel.b() as f32];
+        let mut error = [0.0f32; 3];
+        (0..plan_size)
+            .map(|_| {
+                let attempt = std::array::from_fn::<u8, 3, _>(|channel| {
+                    (value[channel] + error[channel] * Self::KNOLL_ERROR_MULTIPLIER)
+                        .clamp(0.0, 255.0)
+                        .round() as u8
+                });
+                let attempt = Color32::from_rgb(attempt[0], attempt[1], attempt[2]);
+                let candidate = matcher.find_closest_color(attempt);
+                error[0] += value[0] - candidate.r() as f32;
+                error[1] += value[1] - candidate.g() as f32;
+                error[2] += value[2] - candidate.b() as f32;
+                candidate
+            })
+            .collect()
+    }
+
+    // Yliluoma's positional dithering (algorithm 2): colors are added to the plan (possibly many copies at once),
+    // so that the average of the plan is as close to the pixel as possible
+    fn yliluoma_plan(pixel: Color32, matcher: &PaletteMatcher, plan_size: usize) -> Vec<Color32> {
+        let value = PaletteMatcher::convert(pixel, matcher.metric);
+        let mut plan = Vec::with_capacity(plan_size);
+        let mut sum_so_far = [0.0f32; 3];
+        while plan.len() < plan_size {
+            let mut best = (f32::MAX, matcher.colors[0], 1);
+            let max_count = plan.len().max(1);
+            for &color in &matcher.colors {
+                let color_value = [color.r() as f32, color.g() as f32, color.b() as f32];
+                let mut count = 1;
+                while count <= max_count {
+                    let total = (plan.len() + count) as f32;
+                    let mean = std::array::from_fn(|channel| {
+                        (sum_so_far[channel] + color_value[channel] * count as f32) / total
+                    });
+                    let mean = PaletteMatcher::convert_value(mean, matcher.metric);
+                    let penalty = matcher.distance(value, mean);
+                    if penalty < best.0 {
+                        best = (penalty, color, count);
+                    }
+                    count *= 2;
+                }
+            }
+            let (_, color, count) = best;
+            let count = count.min(plan_size - plan.len());
+            plan.resize(plan.len() + count, color);
+            sum_so_far[0] += color.r() as f32 * count as f32;
+            sum_so_far[1] += color.g() as f32 * count as f32;
+            sum_so_far[2] += color.b() as f32 * count as f32;
+        }
+        plan
+    }
+
+    // Returns candidates sorted by luminance, threshold matrix selects one of them
+    fn mixing_plan(
+        method: PatternDitheringMethod,
+        pixel: Color32,
+        matcher: &PaletteMatcher,
+        plan_size: usize,
+    ) -> Vec<Color32> {
+        let mut plan = match method {
+            PatternDitheringMethod::Knoll => Self::knoll_plan(pixel, matcher, plan_size),
+            PatternDitheringMethod::Yliluoma => Self::yliluoma_plan(pixel, matcher, plan_size),
+        };
+        plan.sort_by_key(|&color| Self::luminance(color));
+        plan
+    }
+}
+
+impl OrderedDitheringCommon for PatternDitheringColorQuantizer {
+    fn matrix_position(x: usize, y: usize, n: usize, _seed: u64) -> (usize, usize) {
+        (x % n, y % n)
+    }
+}
+
+impl ColorQuantizer for PatternDitheringColorQuantizer {
+    type Params = PatternDitheringParameters;
+
+    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
+        let palette = Palette::build(&params.palette, initial_image);
+        let matcher =
+            Self::limit_palette(PaletteMatcher::new(palette.colors, params.palette.metric()));
+        let n = params.size.side();
+        let matrix = Self::generate_matrix(n);
+        let plan_size = n * n;
+
+        // Plans are expensive, so they are computed only once for each unique color of the image
+        let colors: HashSet<Color32> = initial_image.pixels.iter().copied().collect();
+        let plans: HashMap<Color32, Vec<Color32>> = colors
+            .into_par_iter()
+            .map(|color| {
+                let plan = Self::mixing_plan(params.method, color, &matcher, plan_size);
+                (color, plan)
+            })
+            .collect();
+
+        const CHUNK_SIZE: usize = 512;
+
+        let size = initial_image.size;
+
+        let output_pixels: Vec<_> = initial_image
+            .pixels
+            .par_chunks(CHUNK_SIZE)
+            .enumerate()
+            .flat_map(|(chunk_id, chunk)| {
                 chunk
                     .iter()
                     .enumerate()
-                    .flat_map(|(pixel_id, &pixel)| {
+                    .flat_map(|(pixel_id, pixel)| {
                         let id = chunk_id * CHUNK_SIZE + pixel_id;
                         let x = id / size[0];
                         let y = id - x * size[0];
-                        let new_r = Self::get_color(pixel.r(), &r_levels, &m_r, x, y, n_r as usize);
-                        let new_g = Self::get_color(pixel.g(), &g_levels, &m_g, x, y, n_g as usize);
-                        let new_b = Self::get_color(pixel.b(), &b_levels, &m_b, x, y, n_b as usize);
-                        let new_pixel = Color32::from_rgb(new_r, new_g, new_b);
-                        new_pixel.to_array()
+                        let plan = &plans[pixel];
+                        let (i, j) = Self::matrix_position(x, y, n, 0);
+                        plan[matrix[i * n + j] as usize].to_array()
                     })
                     .collect::<Vec<_>>()
             })
             .collect();
 
-        ColorImage::from_rgba_unmultiplied(size, output_pixesl.as_slice())
+        ColorImage::from_rgba_unmultiplied(size, output_pixels.as_slice())
     }
 }
 
-pub struct OrderedDitheringRelativeColorQuantizer;
+pub struct HalftoneColorQuantizer;
 
-impl OrderedDitheringCommon for OrderedDitheringRelativeColorQuantizer {
-    fn get_color(
-        value: u8,
-        levels: &[u8],
-        matrix: &[Vec<u32>],
+impl HalftoneColorQuantizer {
+    // Number of samples along each side of a cell used to equalize spot function
+    const CELL_SAMPLES: usize = 64;
+    // Smallest size of a cell in pixels
+    const MIN_CELL_SIZE: f32 = 2.0;
+
+    // Spot function for the position inside a cell, both coordinates are from -1 to 1 and (0, 0) is the center,
+    // lower values are covered by the dot first
+    fn spot_value(spot_function: HalftoneSpotFunction, u: f32, v: f32) -> f32 {
+        match spot_function {
+            HalftoneSpotFunction::Round => u * u + v * v,
+            HalftoneSpotFunction::Elliptical => u * u + (v * v) / 0.5,
+            HalftoneSpotFunction::Line => v.abs(),
+        }
+    }
+
+    // Sorted values of the spot function sampled over the whole cell, position of the value among them
+    // is used as threshold, so that area covered by the dot grows linearly with the tone
+    fn sampled_spot_values(spot_function: HalftoneSpotFunction) -> Vec<f32> {
+        let to_cell = |i: usize| (i as f32 + 0.5) / Self::CELL_SAMPLES as f32 * 2.0 - 1.0;
+        let mut values: Vec<f32> = (0..Self::CELL_SAMPLES * Self::CELL_SAMPLES)
+            .map(|id| {
+                let u = to_cell(id % Self::CELL_SAMPLES);
+                let v = to_cell(id / Self::CELL_SAMPLES);
+                Self::spot_value(spot_function, u, v)
+            })
+            .collect();
+        values.sort_by(|lhs, rhs| lhs.total_cmp(rhs));
+        values
+    }
+
+    // Returns threshold of the pixel at (x, y) in a screen rotated by `angle` degrees, like thresholds
+    // of ordered dithering, `x` is the row and `y` is the column of the pixel
+    fn threshold(
+        spot_values: &[f32],
+        spot_function: HalftoneSpotFunction,
+        cell_size: f32,
+        angle: u16,
         x: usize,
         y: usize,
-        n: usize,
-    ) -> u8 {
-        let n_sq = n * n;
-        let scaled_value = value as usize * (levels.len() - 1);
-        let col = scaled_value / 255;
-        let re = scaled_value % 255;
-        let i = x % n;
-        let j = y % n;
-        let final_col = if re > (matrix[i][j] as usize * 255 / n_sq) {
-            col + 1
-        } else {
-            col
-        };
-        levels[final_col]
+    ) -> (usize, usize) {
+        let (sin, cos) = (angle as f32).to_radians().sin_cos();
+        let (row, col) = (x as f32 + 0.5, y as f32 + 0.5);
+        let u = (col * cos + row * sin) / cell_size;
+        let v = (row * cos - col * sin) / cell_size;
+        let u = (u - u.floor()) * 2.0 - 1.0;
+        let v = (v - v.floor()) * 2.0 - 1.0;
+        let value = Self::spot_value(spot_function, u, v);
+        let rank = spot_values.partition_point(|&sample| sample < value);
+        // Dark dot grows from the center of a cell as the tone gets darker, so the center has the highest threshold
+        (spot_values.len() - rank, spot_values.len() + 1)
+    }
+
+    // Gray component of each pixel is screened with the black (K) screen like in CMYK printing,
+    // pixels covered by a black dot become black, and the others keep only their chromatic part,
+    // brightened so that their brightest channel is white
+    fn separate_black(
+        linear_light: bool,
+        initial_image: &ColorImage,
+        black_threshold: impl Fn(usize, usize) -> (usize, usize) + Sync,
+    ) -> ColorImage {
+        let width = initial_image.size[0];
+        let pixels = initial_image
+            .pixels
+            .par_iter()
+            .enumerate()
+            .map(|(id, pixel)| {
+                let (x, y) = (id / width, id % width);
+                let values = [pixel.r(), pixel.g(), pixel.b()];
+                let max = values.into_iter().max().unwrap_or(0);
+                let value = if linear_light {
+                    DitheringCommon::linear_light_value(max, &[0, 255])
+                } else {
+                    max
+                };
+                if Self::get_level(value, &[0, 255], black_threshold(x, y)) == 0 {
+                    return Color32::BLACK;
+                }
+                let [r, g, b] = values.map(|channel| {
+                    if linear_light {
+                        color_spaces::linear_to_srgb(
+                            color_spaces::srgb_to_linear(channel)
+                                / color_spaces::srgb_to_linear(max),
+                        )
+                    } else {
+                        (channel as f32 * 255.0 / max as f32).round() as u8
+                    }
+                });
+                Color32::from_rgb(r, g, b)
+            })
+            .collect();
+        ColorImage {
+            size: initial_image.size,
+            pixels,
+        }
     }
 }
 
-impl ColorQuantizer for OrderedDitheringRelativeColorQuantizer {
-    type Params = DitheringParameters;
+impl OrderedDitheringCommon for HalftoneColorQuantizer {
+    fn matrix_position(x: usize, y: usize, n: usize, _seed: u64) -> (usize, usize) {
+        (x % n, y % n)
+    }
+}
+
+impl ColorQuantizer for HalftoneColorQuantizer {
+    type Params = HalftoneParameters;
 
     fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
-        Self::ordered_dithering_output_image(params, initial_image)
+        let spot_values = Self::sampled_spot_values(params.spot_function);
+        let cell_size =
+            (params.dpi as f32 / params.frequency.max(1) as f32).max(Self::MIN_CELL_SIZE);
+        let threshold = |channel: usize, x: usize, y: usize| {
+            let angle = params.angles[channel];
+            Self::threshold(&spot_values, params.spot_function, cell_size, angle, x, y)
+        };
+
+        match &params.target {
+            DitheringTarget::Levels => {
+                let chromatic_image =
+                    Self::separate_black(params.dithering.linear_light, initial_image, |x, y| {
+                        threshold(3, x, y)
+                    });
+                Self::ordered_dithering_levels_output_image(
+                    params.dithering,
+                    &chromatic_image,
+                    threshold,
+                )
+            }
+            // Palette colors can't be separated into channels, so only the black screen is used
+            DitheringTarget::Palette(source) => {
+                let palette = Palette::build(source, initial_image);
+                let matcher = PaletteMatcher::new(palette.colors, source.metric());
+                Self::ordered_dithering_palette_output_image(&matcher, initial_image, |x, y| {
+                    threshold(3, x, y)
+                })
+            }
+        }
     }
 }
 
-pub struct OrderedDitheringRandomColorQuantizer;
+// Transparency is handled separately from colors: algorithms work on the image with alpha removed,
+// and alpha processed according to the chosen mode is restored in their output
+pub struct AlphaQuantizer;
 
-impl OrderedDitheringCommon for OrderedDitheringRandomColorQuantizer {
-    fn get_color(
-        value: u8,
-        levels: &[u8],
-        matrix: &[Vec<u32>],
-        _x: usize,
-        _y: usize,
-        n: usize,
-    ) -> u8 {
-        let mut rng = rand::thread_rng();
-        let n_sq = n * n;
-        let scaled_value = value as usize * (levels.len() - 1);
-        let col = scaled_value / 255;
-        let re = scaled_value % 255;
-        let i: usize = rng.gen_range(0..=(n - 1));
-        let j: usize = rng.gen_range(0..=(n - 1));
-        let final_col = if re > (matrix[i][j] as usize * 255 / n_sq) {
-            col + 1
-        } else {
-            col
-        };
-        levels[final_col]
+impl AlphaQuantizer {
+    // Returns the image with all pixels opaque, colors are unmultiplied so that transparency doesn't darken them
+    // Pixels which are fully transparent after alpha quantization (`alpha` is the result of `quantize_alpha`)
+    // are masked with zero alpha instead, palette algorithms skip them and their color (copied from
+    // the last visible pixel) only keeps dithering smooth
+    pub fn opaque_image(initial_image: &ColorImage, alpha: &[u8]) -> ColorImage {
+        let mut last_opaque = Color32::BLACK;
+        let pixels: Vec<_> = initial_image
+            .pixels
+            .iter()
+            .zip(alpha)
+            .map(|(pixel, &a)| {
+                if a == 0 {
+                    let [r, g, b, _] = last_opaque.to_array();
+                    return Color32::from_rgba_premultiplied(r, g, b, 0);
+                }
+                let [r, g, b, _] = pixel.to_srgba_unmultiplied();
+                last_opaque = Color32::from_rgb(r, g, b);
+                last_opaque
+            })
+            .collect();
+        ColorImage {
+            size: initial_image.size,
+            pixels,
+        }
+    }
+
+    pub fn quantize_alpha(params: AlphaParameters, initial_image: &ColorImage) -> Vec<u8> {
+        let size = initial_image.size;
+        match params.mode {
+            AlphaMode::Preserve => initial_image.pixels.iter().map(|p| p.a()).collect(),
+            AlphaMode::Threshold | AlphaMode::TransparentIndex => initial_image
+                .pixels
+                .iter()
+                .map(|p| if p.a() < params.threshold { 0 } else { 255 })
+                .collect(),
+            AlphaMode::Dither => {
+                let levels = DitheringCommon::generate_color_levels(params.k_a.max(2));
+                let (n, matrix) = Self::bayer_matrix(levels.len() as u8, BayerMatrixSize::Auto);
+                initial_image
+                    .pixels
+                    .par_iter()
+                    .enumerate()
+                    .map(|(id, p)| {
+                        let (x, y) = (id / size[0], id % size[0]);
+                        Self::get_color(p.a(), &levels, &matrix, x, y, n, 0)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Combines colors of the processed image with quantized alpha,
+    // in transparent palette index mode all transparent pixels share a single color
+    pub fn apply_alpha(
+        params: AlphaParameters,
+        processed_image: &ColorImage,
+        alpha: &[u8],
+    ) -> ColorImage {
+        let pixels: Vec<_> = processed_image
+            .pixels
+            .iter()
+            .zip(alpha)
+            .flat_map(|(pixel, &a)| {
+                if params.mode == AlphaMode::TransparentIndex && a == 0 {
+                    return Color32::TRANSPARENT.to_array();
+                }
+                [pixel.r(), pixel.g(), pixel.b(), a]
+            })
+            .collect();
+        ColorImage::from_rgba_unmultiplied(processed_image.size, pixels.as_slice())
     }
 }
 
-impl ColorQuantizer for OrderedDitheringRandomColorQuantizer {
-    type Params = DitheringParameters;
+impl OrderedDitheringCommon for AlphaQuantizer {
+    fn matrix_position(x: usize, y: usize, n: usize, _seed: u64) -> (usize, usize) {
+        (x % n, y % n)
+    }
+}
 
-    fn generate_output_image(params: Self::Params, initial_image: &ColorImage) -> ColorImage {
-        Self::ordered_dithering_output_image(params, initial_image)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::{BuiltinPalette, FixedPalette};
+    use strum::IntoEnumIterator;
+
+    // Image with many unique colors, so that parallel iterators split the work into many chunks
+    fn gradient_image(width: usize, height: usize) -> ColorImage {
+        let pixels: Vec<u8> = (0..width * height)
+            .flat_map(|id| {
+                let (x, y) = (id % width, id / width);
+                [
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    ((x ^ y) % 256) as u8,
+                    255,
+                ]
+            })
+            .collect();
+        ColorImage::from_rgba_unmultiplied([width, height], &pixels)
+    }
+
+    fn flat_image(width: usize, height: usize, value: u8) -> ColorImage {
+        ColorImage::new([width, height], Color32::from_gray(value))
+    }
+
+    // Share of white pixels in an image dithered to black and white
+    fn white_share(image: &ColorImage) -> f32 {
+        let white = image
+            .pixels
+            .iter()
+            .filter(|&&pixel| pixel == Color32::WHITE)
+            .count();
+        white as f32 / image.pixels.len() as f32
+    }
+
+    #[test]
+    fn error_diffusion_keeps_average_of_dark_gray() {
+        // Negative errors of white pixels have to be diffused too, otherwise there are too many of them
+        let image = flat_image(64, 64, 40);
+        let output = ErrorDiffusionDitheringColorQuantizer::generate_output_image(
+            Default::default(),
+            &image,
+        );
+        let share = white_share(&output);
+        assert!((share - 40.0 / 255.0).abs() < 0.01, "{}", share);
+    }
+
+    #[test]
+    fn error_diffusion_with_zero_error_clamp_is_thresholding() {
+        let image = gradient_image(64, 64);
+        let params = ErrorDiffusionParameters {
+            error_clamp: Some(0),
+            ..Default::default()
+        };
+        let output = ErrorDiffusionDitheringColorQuantizer::generate_output_image(params, &image);
+        let thresholded =
+            AverageDitheringColorQuantizer::generate_output_image(Default::default(), &image);
+        assert_eq!(output.pixels, thresholded.pixels);
+    }
+
+    #[test]
+    fn serpentine_doesnt_change_single_column() {
+        let image = gradient_image(1, 64);
+        for kernel in ErrorDiffusionKernel::iter() {
+            let output = |serpentine| {
+                let params = ErrorDiffusionParameters {
+                    kernel,
+                    serpentine,
+                    ..Default::default()
+                };
+                ErrorDiffusionDitheringColorQuantizer::generate_output_image(params, &image).pixels
+            };
+            assert_eq!(output(true), output(false), "{}", kernel);
+        }
+    }
+
+    #[test]
+    fn serpentine_scans_second_row_right_to_left() {
+        // The first row is black, so it doesn't leave any error for the second one
+        let width = 64;
+        let row = gradient_image(width, 1).pixels;
+        let mut image = ColorImage::new([width, 2], Color32::BLACK);
+        image.pixels[width..].copy_from_slice(&row);
+        let mut flipped = image.clone();
+        flipped.pixels[width..].reverse();
+        for kernel in ErrorDiffusionKernel::iter() {
+            let params = |serpentine| ErrorDiffusionParameters {
+                kernel,
+                serpentine,
+                ..Default::default()
+            };
+            let serpentine =
+                ErrorDiffusionDitheringColorQuantizer::generate_output_image(params(true), &image);
+            let mut mirrored = ErrorDiffusionDitheringColorQuantizer::generate_output_image(
+                params(false),
+                &flipped,
+            );
+            mirrored.pixels[width..].reverse();
+            assert_eq!(serpentine.pixels, mirrored.pixels, "{}", kernel);
+        }
+    }
+
+    #[test]
+    fn kernel_weights_sum_to_divisor() {
+        for kernel in ErrorDiffusionKernel::iter() {
+            let (entries, divisor) = ErrorDiffusionDitheringColorQuantizer::kernel_entries(kernel);
+            let sum: f32 = entries.iter().map(|&(_, _, weight)| weight).sum();
+            // Atkinson diffuses only 6/8 of the error
+            let expected = match kernel {
+                ErrorDiffusionKernel::Atkinson => divisor * 6.0 / 8.0,
+                _ => divisor,
+            };
+            assert_eq!(sum, expected, "{}", kernel);
+        }
+    }
+
+    #[test]
+    fn kernels_dont_point_to_processed_pixels() {
+        for kernel in ErrorDiffusionKernel::iter() {
+            let (entries, _) = ErrorDiffusionDitheringColorQuantizer::kernel_entries(kernel);
+            for &(column_offset, row_offset, _) in entries {
+                assert!(
+                    row_offset > 0 || column_offset > 0,
+                    "{} ({}, {})",
+                    kernel,
+                    column_offset,
+                    row_offset
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bayer_matrices_are_permutations() {
+        for size in BayerMatrixSize::iter() {
+            let Some(n) = size.side() else {
+                continue;
+            };
+            let (side, matrix) = OrderedDitheringRelativeColorQuantizer::bayer_matrix(2, size);
+            assert_eq!(side, n, "{}", size);
+            let mut values = matrix.to_vec();
+            values.sort_unstable();
+            assert!(values.into_iter().eq(0..(n * n) as u32), "{}", size);
+        }
+    }
+
+    #[test]
+    fn auto_bayer_matrix_size_depends_on_levels() {
+        for (k, n) in [
+            (2, 16),
+            (3, 12),
+            (4, 12),
+            (5, 8),
+            (9, 6),
+            (17, 4),
+            (30, 3),
+            (255, 2),
+        ] {
+            let (side, _) =
+                OrderedDitheringRelativeColorQuantizer::bayer_matrix(k, BayerMatrixSize::Auto);
+            assert_eq!(side, n, "{}", k);
+        }
+        // Smallest matrix which has at least 256 thresholds between all pairs of levels
+        for k in 2..=255u8 {
+            let expected = [2, 3, 4, 6, 8, 12, 16]
+                .into_iter()
+                .find(|&n| n * n * (k as usize - 1) >= 256)
+                .unwrap_or(16);
+            let (side, _) =
+                OrderedDitheringRelativeColorQuantizer::bayer_matrix(k, BayerMatrixSize::Auto);
+            assert_eq!(side, expected, "{}", k);
+        }
+    }
+
+    #[test]
+    fn hilbert_walk_visits_every_pixel_once() {
+        let size = [5, 3];
+        let points: Vec<_> = RiemersmaDitheringColorQuantizer::hilbert_walk(size).collect();
+        let unique: HashSet<_> = points.iter().copied().collect();
+        assert_eq!(points.len(), size[0] * size[1]);
+        assert_eq!(unique.len(), size[0] * size[1]);
+    }
+
+    #[test]
+    fn riemersma_keeps_flat_image_at_level() {
+        // Levels of k = 4 are 0, 85, 170 and 255
+        let params = RiemersmaParameters {
+            dithering: DitheringParameters {
+                k_r: 4,
+                k_g: 4,
+                k_b: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for value in [0, 85, 170, 255] {
+            let image = flat_image(16, 16, value);
+            let output =
+                RiemersmaDitheringColorQuantizer::generate_output_image(params.clone(), &image);
+            assert_eq!(output.pixels, image.pixels, "{}", value);
+        }
+    }
+
+    #[test]
+    fn linear_light_dithering_matches_linear_intensity() {
+        // sRGB 128 has linear intensity of about 0.216
+        let image = flat_image(64, 64, 128);
+        for (linear_light, expected) in [(false, 0.5), (true, 0.216)] {
+            let dithering = DitheringParameters {
+                linear_light,
+                ..Default::default()
+            };
+            let error_diffusion = ErrorDiffusionDitheringColorQuantizer::generate_output_image(
+                ErrorDiffusionParameters {
+                    dithering,
+                    ..Default::default()
+                },
+                &image,
+            );
+            let ordered = OrderedDitheringRelativeColorQuantizer::generate_output_image(
+                OrderedDitheringParameters {
+                    dithering,
+                    ..Default::default()
+                },
+                &image,
+            );
+            for output in [error_diffusion, ordered] {
+                let share = white_share(&output);
+                assert!(
+                    (share - expected).abs() < 0.01,
+                    "{} {}",
+                    linear_light,
+                    share
+                );
+            }
+        }
+        // Without dithering the closest level is chosen, which is black in linear light
+        for (linear_light, expected) in [(false, Color32::WHITE), (true, Color32::BLACK)] {
+            let dithering = DitheringParameters {
+                linear_light,
+                ..Default::default()
+            };
+            let output = AverageDitheringColorQuantizer::generate_output_image(dithering, &image);
+            assert!(output.pixels.iter().all(|&pixel| pixel == expected));
+        }
+    }
+
+    #[test]
+    fn median_cut_finds_every_flat_region() {
+        let regions = [
+            Color32::from_rgb(200, 30, 30),
+            Color32::from_rgb(30, 200, 30),
+            Color32::from_rgb(30, 30, 200),
+            Color32::from_rgb(220, 220, 40),
+            Color32::from_rgb(90, 90, 90),
+        ];
+        let pixels: Vec<Color32> = regions
+            .iter()
+            .flat_map(|&color| std::iter::repeat_n(color, 64))
+            .collect();
+        let image = ColorImage {
+            size: [64, regions.len()],
+            pixels,
+        };
+        let params = MedianCutParameters {
+            k: 16,
+            ..Default::default()
+        };
+        let mut palette = MedianCutColorQuantizer::build_palette(params, &image).colors;
+        palette.sort_unstable_by_key(|color| color.to_array());
+        let mut expected = regions.to_vec();
+        expected.sort_unstable_by_key(|color| color.to_array());
+        assert_eq!(palette, expected);
+    }
+
+    #[test]
+    fn median_cut_of_fully_masked_image_has_single_color() {
+        let image = ColorImage::new([4, 4], Color32::TRANSPARENT);
+        let palette = MedianCutColorQuantizer::build_palette(Default::default(), &image);
+        assert_eq!(palette.colors, vec![Color32::BLACK]);
+    }
+
+    #[test]
+    fn octree_returns_at_most_k_colors() {
+        let image = gradient_image(64, 64);
+        for k in [1, 2, 16, 100] {
+            let params = OctreeParameters {
+                k,
+                ..Default::default()
+            };
+            let palette = OctreeColorQuantizer::build_palette(params, &image);
+            assert!(!palette.colors.is_empty() && palette.colors.len() <= k);
+            let output = OctreeColorQuantizer::generate_output_image(params, &image);
+            let colors: HashSet<Color32> = output.pixels.into_iter().collect();
+            assert!(colors.len() <= k);
+        }
+    }
+
+    #[test]
+    fn wu_returns_at_most_k_colors() {
+        let image = gradient_image(64, 64);
+        for k in [1, 2, 16, 100] {
+            let params = WuParameters {
+                k,
+                ..Default::default()
+            };
+            let palette = WuColorQuantizer::build_palette(params, &image);
+            assert!(!palette.colors.is_empty() && palette.colors.len() <= k);
+            let output = WuColorQuantizer::generate_output_image(params, &image);
+            let colors: HashSet<Color32> = output.pixels.into_iter().collect();
+            assert!(colors.len() <= k);
+        }
+    }
+
+    #[test]
+    fn neu_quant_returns_at_most_k_colors() {
+        let image = gradient_image(64, 64);
+        for k in [1, 2, 16, 100] {
+            let params = NeuQuantParameters {
+                k,
+                ..Default::default()
+            };
+            let palette = NeuQuantColorQuantizer::build_palette(params, &image);
+            assert!(!palette.colors.is_empty() && palette.colors.len() <= k);
+            let output = NeuQuantColorQuantizer::generate_output_image(params, &image);
+            let colors: HashSet<Color32> = output.pixels.into_iter().collect();
+            assert!(colors.len() <= k);
+        }
+    }
+
+    #[test]
+    fn k_means_is_reproducible_for_the_same_seed() {
+        let image = gradient_image(128, 128);
+        for initialization in [
+            KMeansInitialization::Random,
+            KMeansInitialization::KMeansPlusPlus,
+            KMeansInitialization::Popularity,
+        ] {
+            let params = KMeansParameters {
+                seed: 42,
+                initialization,
+                ..Default::default()
+            };
+            let first = KMeansColorQuantizer::generate_output_image(params, &image);
+            let second = KMeansColorQuantizer::generate_output_image(params, &image);
+            assert_eq!(first.pixels, second.pixels);
+        }
+    }
+
+    #[test]
+    fn random_ordered_dithering_depends_only_on_the_seed() {
+        let image = gradient_image(128, 128);
+        let params = |seed| RandomOrderedDitheringParameters {
+            seed,
+            ..Default::default()
+        };
+        let first = OrderedDitheringRandomColorQuantizer::generate_output_image(params(7), &image);
+        let second = OrderedDitheringRandomColorQuantizer::generate_output_image(params(7), &image);
+        let other = OrderedDitheringRandomColorQuantizer::generate_output_image(params(8), &image);
+        assert_eq!(first.pixels, second.pixels);
+        assert_ne!(first.pixels, other.pixels);
+    }
+
+    #[test]
+    fn halftone_screens_gray_only_with_black_dots() {
+        let gray = Color32::from_rgb(128, 128, 128);
+        let image = ColorImage::new([64, 64], gray);
+        let output = HalftoneColorQuantizer::generate_output_image(Default::default(), &image);
+        assert!(output
+            .pixels
+            .iter()
+            .all(|&pixel| pixel == Color32::BLACK || pixel == Color32::WHITE));
+        let black = output
+            .pixels
+            .iter()
+            .filter(|&&pixel| pixel == Color32::BLACK)
+            .count() as f32;
+        let coverage = black / output.pixels.len() as f32;
+        assert!((coverage - 0.5).abs() < 0.05, "{}", coverage);
+    }
+
+    #[test]
+    fn halftone_maps_to_palette_target() {
+        let image = gradient_image(64, 64);
+        let palette = FixedPalette::Builtin(BuiltinPalette::Pico8);
+        let params = HalftoneParameters {
+            target: DitheringTarget::Palette(PaletteSource::Fixed(FixedPaletteParameters {
+                palette: palette.clone(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let output = HalftoneColorQuantizer::generate_output_image(params, &image);
+        let palette_colors: HashSet<Color32> =
+            Palette::fixed(&palette).colors.into_iter().collect();
+        let output_colors: HashSet<Color32> = output.pixels.into_iter().collect();
+        assert!(output_colors.is_subset(&palette_colors));
+    }
+
+    #[test]
+    fn error_diffusion_maps_to_palette_target() {
+        let image = gradient_image(64, 64);
+        let palette = FixedPalette::Builtin(BuiltinPalette::Pico8);
+        let params = ErrorDiffusionParameters {
+            target: DitheringTarget::Palette(PaletteSource::Fixed(FixedPaletteParameters {
+                palette: palette.clone(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let output = ErrorDiffusionDitheringColorQuantizer::generate_output_image(params, &image);
+        let palette_colors: HashSet<Color32> =
+            Palette::fixed(&palette).colors.into_iter().collect();
+        let output_colors: HashSet<Color32> = output.pixels.into_iter().collect();
+        assert!(output_colors.is_subset(&palette_colors));
+    }
+
+    #[test]
+    fn error_diffusion_to_black_and_white_palette_keeps_average() {
+        let image = flat_image(64, 64, 128);
+        let palette = Palette::new(vec![Color32::BLACK, Color32::WHITE]).into_fixed();
+        let params = ErrorDiffusionParameters {
+            target: DitheringTarget::Palette(PaletteSource::Fixed(FixedPaletteParameters {
+                palette,
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let output = ErrorDiffusionDitheringColorQuantizer::generate_output_image(params, &image);
+        let share = white_share(&output);
+        assert!((share - 128.0 / 255.0).abs() < 0.01, "{}", share);
+    }
+
+    #[test]
+    fn blue_noise_matrix_spreads_both_lowest_and_highest_ranks() {
+        let n = 32;
+        let matrix = OrderedDitheringBlueNoiseColorQuantizer::generate_void_and_cluster_matrix(n);
+        let mut sorted = matrix.clone();
+        sorted.sort_unstable();
+        assert!(sorted
+            .iter()
+            .enumerate()
+            .all(|(id, &rank)| rank == id as u32));
+        // Pixels with the lowest and with the highest ranks shouldn't touch each other
+        let count = (n * n / 10) as u32;
+        for selected in [
+            |rank: u32, count: u32, _: u32| rank < count,
+            |rank: u32, count: u32, total: u32| rank >= total - count,
+        ] {
+            let ids: Vec<usize> = (0..n * n)
+                .filter(|&id| selected(matrix[id], count, (n * n) as u32))
+                .collect();
+            for &lhs in &ids {
+                for &rhs in ids.iter().filter(|&&rhs| rhs != lhs) {
+                    let dy = (lhs / n).abs_diff(rhs / n);
+                    let dx = (lhs % n).abs_diff(rhs % n);
+                    let (dy, dx) = (dy.min(n - dy), dx.min(n - dx));
+                    assert!(dy.max(dx) > 1, "{} {}", lhs, rhs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pattern_dithering_limits_big_palettes() {
+        let image = gradient_image(32, 32);
+        let palette = FixedPalette::Builtin(BuiltinPalette::GameBoyColor);
+        let params = PatternDitheringParameters {
+            method: PatternDitheringMethod::Yliluoma,
+            palette: PaletteSource::Fixed(FixedPaletteParameters {
+                palette: palette.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let output = PatternDitheringColorQuantizer::generate_output_image(params, &image);
+        let palette_colors: HashSet<Color32> =
+            Palette::fixed(&palette).colors.into_iter().collect();
+        let output_colors: HashSet<Color32> = output.pixels.into_iter().collect();
+        assert!(output_colors.is_subset(&palette_colors));
+        assert!(output_colors.len() <= PatternDitheringColorQuantizer::MAX_PALETTE_COLORS);
+    }
+
+    #[test]
+    fn tree_search_matches_linear_search() {
+        let colors = Palette::fixed(&FixedPalette::Builtin(BuiltinPalette::GameBoyColor)).colors;
+        let mut rng = StdRng::seed_from_u64(0);
+        let pixels: Vec<Color32> = gradient_image(16, 16)
+            .pixels
+            .into_iter()
+            .chain((0..256).map(|_| Color32::from_rgb(rng.gen(), rng.gen(), rng.gen())))
+            .collect();
+        for metric in [
+            DistanceMetric::Redmean,
+            DistanceMetric::Cie76,
+            DistanceMetric::Cie94,
+            DistanceMetric::Ciede2000,
+            DistanceMetric::OkLab,
+        ] {
+            let matcher = PaletteMatcher::new(colors.clone(), metric);
+            let tree = matcher
+                .tree
+                .as_ref()
+                .expect("Game Boy Color palette should be searched in the tree");
+            for &pixel in &pixels {
+                assert_eq!(
+                    matcher.find_closest_tree_color(pixel, tree),
+                    matcher.find_closest_linear_color(pixel),
+                    "{:?} {:?}",
+                    metric,
+                    pixel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_dont_affect_palette() {
+        let blue = [0, 0, 255, 255];
+        let green = [0, 255, 0, 255];
+        let transparent = [255, 0, 0, 0];
+        // Stays visible in some modes, but it is less popular than green anyway
+        let faint = [255, 0, 0, 10];
+        let pixels: Vec<u8> = std::iter::once(blue)
+            .chain(std::iter::repeat(transparent).take(99))
+            .chain(std::iter::repeat(green).take(10))
+            .chain(std::iter::repeat(faint).take(5))
+            .flatten()
+            .collect();
+        let image = ColorImage::from_rgba_unmultiplied([115, 1], &pixels);
+        let params = PopularityParameters {
+            k: 1,
+            ..Default::default()
+        };
+        for mode in [
+            AlphaMode::Preserve,
+            AlphaMode::Threshold,
+            AlphaMode::Dither,
+            AlphaMode::TransparentIndex,
+        ] {
+            let alpha = AlphaParameters {
+                mode,
+                ..Default::default()
+            };
+            let quantized_alpha = AlphaQuantizer::quantize_alpha(alpha, &image);
+            let opaque_image = AlphaQuantizer::opaque_image(&image, &quantized_alpha);
+            let palette = PopularityAlgorithmColorQuantizer::build_palette(params, &opaque_image);
+            assert_eq!(palette.colors, vec![Color32::GREEN], "{:?}", mode);
+        }
     }
 }
@@ -0,0 +1,346 @@
+use std::sync::LazyLock;
+
+use egui::Color32;
+
+// Reference white (D65) used for XYZ <-> CIELAB conversions
+const WHITE_X: f32 = 0.95047;
+const WHITE_Y: f32 = 1.0;
+const WHITE_Z: f32 = 1.08883;
+
+// Values of `srgb_to_linear` for every possible channel value, as `powf` is expensive
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|value| {
+        let value = value as f32 / 255.0;
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    })
+});
+
+// Converts sRGB channel value to linear light intensity from 0 to 1
+pub fn srgb_to_linear(value: u8) -> f32 {
+    SRGB_TO_LINEAR[value as usize]
+}
+
+// Converts linear light intensity (clamped to range from 0 to 1) to sRGB channel value
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round() as u8
+}
+
+pub fn color_to_linear_rgb(color: Color32) -> [f32; 3] {
+    [
+        srgb_to_linear(color.r()),
+        srgb_to_linear(color.g()),
+        srgb_to_linear(color.b()),
+    ]
+}
+
+pub fn linear_rgb_to_color(rgb: [f32; 3]) -> Color32 {
+    Color32::from_rgb(
+        linear_to_srgb(rgb[0]),
+        linear_to_srgb(rgb[1]),
+        linear_to_srgb(rgb[2]),
+    )
+}
+
+pub fn linear_rgb_to_xyz([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+    ]
+}
+
+pub fn xyz_to_linear_rgb([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.969266 * x + 1.8760108 * y + 0.041556 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+pub fn xyz_to_lab([x, y, z]: [f32; 3]) -> [f32; 3] {
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x / WHITE_X), f(y / WHITE_Y), f(z / WHITE_Z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+pub fn lab_to_xyz([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let f_inv = |t: f32| {
+        if t * t * t > 216.0 / 24389.0 {
+            t * t * t
+        } else {
+            (116.0 * t - 16.0) * 27.0 / 24389.0
+        }
+    };
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    [
+        f_inv(fx) * WHITE_X,
+        f_inv(fy) * WHITE_Y,
+        f_inv(fz) * WHITE_Z,
+    ]
+}
+
+pub fn linear_rgb_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222146 * r + 0.53633255 * g + 0.051445995 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171885 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+pub fn oklab_to_linear_rgb([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = l + 0.39633778 * a + 0.21580376 * b;
+    let m_ = l - 0.105561346 * a - 0.06385417 * b;
+    let s_ = l - 0.08948418 * a - 1.2914855 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+        -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+        -0.0041960864 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+}
+
+pub fn color_to_lab(color: Color32) -> [f32; 3] {
+    xyz_to_lab(linear_rgb_to_xyz(color_to_linear_rgb(color)))
+}
+
+pub fn color_to_oklab(color: Color32) -> [f32; 3] {
+    linear_rgb_to_oklab(color_to_linear_rgb(color))
+}
+
+pub fn squared_euclidean(lhs: [f32; 3], rhs: [f32; 3]) -> f32 {
+    lhs.iter()
+        .zip(rhs)
+        .map(|(lhs, rhs)| (lhs - rhs) * (lhs - rhs))
+        .sum()
+}
+
+// Weighted euclidean distance in sRGB, approximating perceptual difference cheaply
+// (https://www.compuphase.com/cmetric.htm), returns squared distance
+pub fn redmean_squared([r1, g1, b1]: [f32; 3], [r2, g2, b2]: [f32; 3]) -> f32 {
+    let r_mean = (r1 + r2) / 2.0;
+    let (dr, dg, db) = (r1 - r2, g1 - g2, b1 - b2);
+    (2.0 + r_mean / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - r_mean) / 256.0) * db * db
+}
+
+// CIE94 color difference (with graphic arts weights) between two CIELAB colors, the first one is the reference
+pub fn cie94([l1, a1, b1]: [f32; 3], [l2, a2, b2]: [f32; 3]) -> f32 {
+    const K1: f32 = 0.045;
+    const K2: f32 = 0.015;
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let dl = l1 - l2;
+    let dc = c1 - c2;
+    let da = a1 - a2;
+    let db = b1 - b2;
+    // Squared hue difference, may be slightly negative due to rounding errors
+    let dh_sq = (da * da + db * db - dc * dc).max(0.0);
+    let sc = 1.0 + K1 * c1;
+    let sh = 1.0 + K2 * c1;
+    (dl * dl + (dc / sc) * (dc / sc) + dh_sq / (sh * sh)).sqrt()
+}
+
+// Weights of squared differences of L, a and b from the reference color, their weighted sum is never
+// bigger than the squared CIE94 difference, because chroma and hue weights are at most 1 / S_C
+pub fn cie94_lower_bound_weights([_, a, b]: [f32; 3]) -> [f32; 3] {
+    let sc = 1.0 + 0.045 * (a * a + b * b).sqrt();
+    [1.0, 1.0 / (sc * sc), 1.0 / (sc * sc)]
+}
+
+// Like `cie94_lower_bound_weights`, but for CIEDE2000 and colors with chroma at most `max_chroma`,
+// see `ciede2000_lower_bound`
+pub fn ciede2000_lower_bound_weights([_, a, b]: [f32; 3], max_chroma: f32) -> [f32; 3] {
+    let sc = ciede2000_max_sc((a * a + b * b).sqrt(), max_chroma);
+    let chroma_weight = CIEDE2000_MIN_ROTATION_FACTOR / (sc * sc);
+    [
+        1.0 / (CIEDE2000_MAX_SL * CIEDE2000_MAX_SL),
+        chroma_weight,
+        chroma_weight,
+    ]
+}
+
+// Biggest S_L of CIEDE2000, reached for mean lightness 0 or 100
+const CIEDE2000_MAX_SL: f32 = 1.75;
+// Rotation term makes the sum of squared chroma and hue differences at most 1 - sin(60°) times smaller
+const CIEDE2000_MIN_ROTATION_FACTOR: f32 = 0.13;
+
+// Upper bound of S_C of CIEDE2000 for colors with given chroma, scaling of a* increases chroma at most 1.5 times
+fn ciede2000_max_sc(c1: f32, c2: f32) -> f32 {
+    1.0 + 0.045 * 1.5 * (c1 + c2) / 2.0
+}
+
+// Cheap lower bound of CIEDE2000 difference, S_H is never bigger than S_C, and scaling of a* only increases differences
+pub fn ciede2000_lower_bound([l1, a1, b1]: [f32; 3], [l2, a2, b2]: [f32; 3]) -> f32 {
+    let l_offset = ((l1 + l2) / 2.0 - 50.0) * ((l1 + l2) / 2.0 - 50.0);
+    let sl = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+    let sc = ciede2000_max_sc((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    let (dl, da, db) = (l1 - l2, a1 - a2, b1 - b2);
+    ((dl / sl) * (dl / sl) + CIEDE2000_MIN_ROTATION_FACTOR * (da * da + db * db) / (sc * sc)).sqrt()
+}
+
+// CIEDE2000 color difference between two CIELAB colors
+pub fn ciede2000([l1, a1, b1]: [f32; 3], [l2, a2, b2]: [f32; 3]) -> f32 {
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let c_mean = (c1 + c2) / 2.0;
+    let c_mean_7 = c_mean.powi(7);
+    let g = 0.5 * (1.0 - (c_mean_7 / (c_mean_7 + 25.0f32.powi(7))).sqrt());
+    let a1 = a1 * (1.0 + g);
+    let a2 = a2 * (1.0 + g);
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1 = hue(b1, a1);
+    let h2 = hue(b2, a2);
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh.to_radians() / 2.0).sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.2 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let c_mean_7 = c_mean.powi(7);
+    let rc = 2.0 * (c_mean_7 / (c_mean_7 + 25.0f32.powi(7))).sqrt();
+    let l_offset = (l_mean - 50.0) * (l_mean - 50.0);
+    let sl = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+    let sc = 1.0 + 0.045 * c_mean;
+    let sh = 1.0 + 0.015 * c_mean * t;
+    let rt = -(2.0 * d_theta).to_radians().sin() * rc;
+
+    let (dl, dc, dh) = (dl / sl, dc / sc, dh / sh);
+    (dl * dl + dc * dc + dh * dh + rt * dc * dh).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Colors spread over the whole RGB cube
+    fn test_colors() -> impl Iterator<Item = Color32> {
+        (0..=255u8)
+            .step_by(15)
+            .flat_map(|r| (0..=255u8).step_by(15).map(move |g| (r, g)))
+            .flat_map(|(r, g)| {
+                (0..=255u8)
+                    .step_by(15)
+                    .map(move |b| Color32::from_rgb(r, g, b))
+            })
+    }
+
+    #[test]
+    fn srgb_to_linear_round_trips() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn lab_round_trips() {
+        for color in test_colors() {
+            let lab = color_to_lab(color);
+            let rgb = xyz_to_linear_rgb(lab_to_xyz(lab));
+            assert_eq!(linear_rgb_to_color(rgb), color, "{:?}", lab);
+        }
+    }
+
+    #[test]
+    fn oklab_round_trips() {
+        for color in test_colors() {
+            let oklab = color_to_oklab(color);
+            let rgb = oklab_to_linear_rgb(oklab);
+            assert_eq!(linear_rgb_to_color(rgb), color, "{:?}", oklab);
+        }
+    }
+
+    #[test]
+    fn ciede2000_matches_reference_values() {
+        // Pairs from "The CIEDE2000 Color-Difference Formula" by Sharma, Wu and Dalal
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+            ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+            (
+                [22.7233, 20.0904, -46.694],
+                [23.0331, 14.973, -42.5619],
+                2.0373,
+            ),
+            (
+                [90.9257, -0.5406, -0.9208],
+                [88.6381, -0.8985, -0.7239],
+                1.5381,
+            ),
+        ];
+        for (lhs, rhs, expected) in pairs {
+            assert!(
+                (ciede2000(lhs, rhs) - expected).abs() < 1e-3,
+                "{:?} {:?}",
+                lhs,
+                rhs
+            );
+            assert!(
+                (ciede2000(rhs, lhs) - expected).abs() < 1e-3,
+                "{:?} {:?}",
+                rhs,
+                lhs
+            );
+        }
+    }
+}
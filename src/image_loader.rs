@@ -1,8 +1,8 @@
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use egui::ColorImage;
-use image::ImageReader;
+use image::{DynamicImage, ImageFormat, ImageReader, RgbaImage};
 
 pub fn load_image_from_path<P: AsRef<Path>>(path: P) -> Result<ColorImage> {
     let image = ImageReader::open(path)?.decode()?;
@@ -11,3 +11,26 @@ pub fn load_image_from_path<P: AsRef<Path>>(path: P) -> Result<ColorImage> {
     let pixels = image_buffer.as_flat_samples();
     Ok(ColorImage::from_rgba_unmultiplied(size, pixels.as_slice()))
 }
+
+// Format of the image is deduced from the extension of the path
+pub fn save_image_to_path<P: AsRef<Path>>(path: P, image: &ColorImage) -> Result<()> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)
+        .with_context(|| format!("unsupported image format of {}", path.display()))?;
+    let pixels = image
+        .pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+        .collect();
+    let [width, height] = image.size;
+    let image_buffer = RgbaImage::from_raw(width as _, height as _, pixels)
+        .context("image size doesn't match its pixels")?;
+    let mut image = DynamicImage::ImageRgba8(image_buffer);
+    // JPEG has no alpha channel
+    if format == ImageFormat::Jpeg {
+        image = DynamicImage::ImageRgb8(image.to_rgb8());
+    }
+    image
+        .save_with_format(path, format)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
@@ -0,0 +1,329 @@
+use std::{borrow::Cow, collections::HashMap, io::Write, path::Path};
+
+use anyhow::{bail, Context, Result};
+use egui::ColorImage;
+use rayon::prelude::*;
+
+use crate::{
+    algorithms::{AlphaMode, AlphaParameters, MedianCutParameters},
+    color_quantizers::{AlphaQuantizer, MedianCutColorQuantizer, PaletteBuilder, PaletteMatcher},
+};
+
+// Indexed formats can't store more colors
+pub const MAX_COLORS: usize = 256;
+
+// Image stored as a palette and indices of palette colors of every pixel
+pub struct IndexedImage {
+    pub size: [usize; 2],
+    // Unmultiplied RGBA colors, the ones which aren't fully opaque come first
+    pub palette: Vec<[u8; 4]>,
+    pub indices: Vec<u8>,
+}
+
+impl IndexedImage {
+    // Collects colors actually used by the image, fails when there are more than `MAX_COLORS`
+    pub fn from_color_image(image: &ColorImage) -> Result<IndexedImage> {
+        let colors: Vec<[u8; 4]> = image
+            .pixels
+            .iter()
+            .map(|pixel| match pixel.to_srgba_unmultiplied() {
+                // All fully transparent pixels share a single palette entry
+                [_, _, _, 0] => [0; 4],
+                color => color,
+            })
+            .collect();
+        let mut palette: Vec<[u8; 4]> = Vec::new();
+        let mut color_ids = HashMap::new();
+        for &color in &colors {
+            color_ids.entry(color).or_insert_with(|| {
+                palette.push(color);
+                palette.len() - 1
+            });
+        }
+        if palette.len() > MAX_COLORS {
+            bail!(
+                "image has {} colors, but indexed formats support at most {}",
+                palette.len(),
+                MAX_COLORS
+            );
+        }
+        // PNG stores alpha only for the leading palette entries
+        palette.sort_by_key(|color| color[3] == 255);
+        let color_ids: HashMap<_, _> = palette
+            .iter()
+            .enumerate()
+            .map(|(id, &color)| (color, id as u8))
+            .collect();
+        let indices = colors.iter().map(|color| color_ids[color]).collect();
+        Ok(IndexedImage {
+            size: image.size,
+            palette,
+            indices,
+        })
+    }
+
+    // Like `from_color_image`, but image with too many colors is requantized instead of failing
+    pub fn from_color_image_requantized(image: &ColorImage) -> Result<IndexedImage> {
+        Self::from_color_image(image).or_else(|_| Self::from_color_image(&Self::requantize(image)?))
+    }
+
+    // Reduces the number of colors with median cut so that the image fits into a palette,
+    // alpha becomes binary as all transparent pixels have to share a single palette entry
+    pub fn requantize(image: &ColorImage) -> Result<ColorImage> {
+        if image.pixels.is_empty() {
+            bail!("image is empty, there are no colors to requantize");
+        }
+        let alpha_params = AlphaParameters {
+            mode: AlphaMode::TransparentIndex,
+            ..Default::default()
+        };
+        let params = MedianCutParameters {
+            k: MAX_COLORS - 1,
+            ..Default::default()
+        };
+        let alpha = AlphaQuantizer::quantize_alpha(alpha_params, image);
+        let opaque_image = AlphaQuantizer::opaque_image(image, &alpha);
+        let palette = MedianCutColorQuantizer::build_palette(params, &opaque_image);
+        let matcher = PaletteMatcher::new(palette.colors, params.metric);
+        let pixels = opaque_image
+            .pixels
+            .par_iter()
+            .map(|&pixel| matcher.find_closest_color(pixel))
+            .collect();
+        let requantized = ColorImage {
+            size: image.size,
+            pixels,
+        };
+        Ok(AlphaQuantizer::apply_alpha(
+            alpha_params,
+            &requantized,
+            &alpha,
+        ))
+    }
+
+    // Smallest bit depth supported by PNG which can index the whole palette
+    pub fn bit_depth(&self) -> u8 {
+        match self.palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    // Indexed images can be saved only in formats with a palette, PNG and GIF
+    pub fn is_supported_path<P: AsRef<Path>>(path: P) -> bool {
+        matches!(
+            Self::extension(path.as_ref()).as_deref(),
+            Some("png" | "gif")
+        )
+    }
+
+    fn extension(path: &Path) -> Option<String> {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+    }
+
+    // Format is deduced from the extension of the path, only PNG and GIF are supported
+    // Image is encoded in memory first, so that nothing is written when encoding fails
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        let result = match Self::extension(path).as_deref() {
+            Some("png") => self.write_png(&mut bytes),
+            Some("gif") => self.write_gif(&mut bytes),
+            _ => bail!(
+                "indexed colors can be saved only as PNG or GIF, not {}",
+                path.display()
+            ),
+        };
+        result
+            .and_then(|()| Ok(std::fs::write(path, bytes)?))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn rgb_palette(&self) -> Vec<u8> {
+        self.palette
+            .iter()
+            .flat_map(|&[r, g, b, _]| [r, g, b])
+            .collect()
+    }
+
+    // Rows of indices packed into bytes, each row starts at a new byte
+    fn packed_indices(&self) -> Vec<u8> {
+        let depth = self.bit_depth() as usize;
+        if depth == 8 {
+            return self.indices.clone();
+        }
+        let width = self.size[0];
+        let row_bytes = (width * depth).div_ceil(8);
+        let mut packed = vec![0; row_bytes * self.size[1]];
+        for (row, row_indices) in self.indices.chunks(width).enumerate() {
+            for (column, &index) in row_indices.iter().enumerate() {
+                let bit = column * depth;
+                let shift = 8 - depth - bit % 8;
+                packed[row * row_bytes + bit / 8] |= index << shift;
+            }
+        }
+        packed
+    }
+
+    fn write_png<W: Write>(&self, writer: W) -> Result<()> {
+        let depth = match self.bit_depth() {
+            1 => png::BitDepth::One,
+            2 => png::BitDepth::Two,
+            4 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        };
+        let mut encoder = png::Encoder::new(writer, self.size[0] as u32, self.size[1] as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(self.rgb_palette());
+        let trns: Vec<u8> = self
+            .palette
+            .iter()
+            .map(|color| color[3])
+            .take_while(|&alpha| alpha != 255)
+            .collect();
+        if !trns.is_empty() {
+            encoder.set_trns(trns);
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.packed_indices())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn write_gif<W: Write>(&self, writer: W) -> Result<()> {
+        let (Ok(width), Ok(height)) = (u16::try_from(self.size[0]), u16::try_from(self.size[1]))
+        else {
+            bail!("GIF supports images at most {0}x{0} pixels", u16::MAX);
+        };
+        // GIF has a single fully transparent palette entry at most
+        let transparent = match self.palette.first() {
+            Some([_, _, _, 0]) => Some(0),
+            _ => None,
+        };
+        if self
+            .palette
+            .iter()
+            .any(|color| color[3] != 0 && color[3] != 255)
+        {
+            bail!("GIF supports only fully opaque or fully transparent pixels");
+        }
+        // Palette is stored in a local color table of the only frame
+        let mut encoder = gif::Encoder::new(writer, width, height, &[])?;
+        let frame = gif::Frame {
+            width,
+            height,
+            palette: Some(self.rgb_palette()),
+            transparent,
+            buffer: Cow::Borrowed(&self.indices),
+            ..Default::default()
+        };
+        encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::Color32;
+
+    use super::*;
+
+    // Image whose pixels have colors with given ids, each id is a different shade of red
+    fn image(size: [usize; 2], ids: &[u8]) -> ColorImage {
+        ColorImage {
+            size,
+            pixels: ids.iter().map(|&id| Color32::from_rgb(id, 0, 0)).collect(),
+        }
+    }
+
+    #[test]
+    fn two_colors_are_packed_into_single_bits() {
+        let indexed = IndexedImage::from_color_image(&image([3, 2], &[0, 1, 0, 1, 1, 0])).unwrap();
+        assert_eq!(indexed.bit_depth(), 1);
+        // Every row starts at a new byte
+        assert_eq!(indexed.packed_indices(), vec![0b0100_0000, 0b1100_0000]);
+    }
+
+    #[test]
+    fn four_colors_are_packed_into_two_bits() {
+        let indexed = IndexedImage::from_color_image(&image([3, 2], &[0, 1, 2, 3, 0, 1])).unwrap();
+        assert_eq!(indexed.bit_depth(), 2);
+        assert_eq!(indexed.packed_indices(), vec![0b0001_1000, 0b1100_0100]);
+    }
+
+    #[test]
+    fn sixteen_colors_are_packed_into_four_bits() {
+        let ids: Vec<u8> = (0..16).collect();
+        let indexed = IndexedImage::from_color_image(&image([16, 1], &ids)).unwrap();
+        assert_eq!(indexed.bit_depth(), 4);
+        assert_eq!(
+            indexed.packed_indices(),
+            vec![0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]
+        );
+    }
+
+    #[test]
+    fn more_than_256_colors_are_rejected() {
+        let pixels: Vec<Color32> = (0..=MAX_COLORS)
+            .map(|id| Color32::from_rgb((id % 256) as u8, (id / 256) as u8, 0))
+            .collect();
+        let image = ColorImage {
+            size: [pixels.len(), 1],
+            pixels,
+        };
+        assert!(IndexedImage::from_color_image(&image).is_err());
+        let requantized = IndexedImage::from_color_image_requantized(&image).unwrap();
+        assert!(requantized.palette.len() <= MAX_COLORS);
+    }
+
+    #[test]
+    fn empty_image_cant_be_requantized() {
+        assert!(IndexedImage::requantize(&image([0, 0], &[])).is_err());
+    }
+
+    // 20 shades of red (so that indices take whole bytes) and a single transparent pixel
+    fn image_with_transparency() -> IndexedImage {
+        let mut color_image = image([21, 1], &(0..21).map(|id| id * 10).collect::<Vec<_>>());
+        color_image.pixels[0] = Color32::TRANSPARENT;
+        IndexedImage::from_color_image(&color_image).unwrap()
+    }
+
+    #[test]
+    fn png_is_decoded_to_the_same_image() {
+        let indexed = image_with_transparency();
+        let mut bytes = Vec::new();
+        indexed.write_png(&mut bytes).unwrap();
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut indices = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut indices).unwrap();
+        let info = reader.info();
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        assert_eq!(
+            info.palette.as_deref(),
+            Some(indexed.rgb_palette().as_slice())
+        );
+        assert_eq!(info.trns.as_deref(), Some([0].as_slice()));
+        assert_eq!(indices, indexed.indices);
+    }
+
+    #[test]
+    fn gif_is_decoded_to_the_same_image() {
+        let indexed = image_with_transparency();
+        let mut bytes = Vec::new();
+        indexed.write_gif(&mut bytes).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes.as_slice()).unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        // GIF palette is padded to a power of two entries
+        let palette = frame.palette.as_deref().unwrap();
+        assert!(palette.starts_with(&indexed.rgb_palette()));
+        assert_eq!(frame.transparent, Some(0));
+        assert_eq!(frame.buffer.as_ref(), indexed.indices.as_slice());
+    }
+}
@@ -1,5 +1,10 @@
 pub mod algorithms;
 pub mod app;
+pub mod builtin_palettes;
 pub mod color_quantizers;
+pub mod color_spaces;
 pub mod image_loader;
+pub mod indexed_image;
+pub mod metrics;
+pub mod palette;
 pub mod processed_images_cache;
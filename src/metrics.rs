@@ -0,0 +1,345 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use egui::{Color32, ColorImage};
+use rayon::prelude::*;
+
+use crate::color_spaces;
+
+// Gaussian window of SSIM, as proposed by Wang et al.
+const SSIM_WINDOW_RADIUS: usize = 5;
+const SSIM_WINDOW_SIGMA: f64 = 1.5;
+// Stabilizing constants of SSIM for luminance from 0 to 255
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+// Weights of scales of MS-SSIM, from the finest one
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+// Differences between the initial and processed image, alpha is ignored except for counting colors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageMetrics {
+    // Mean squared error of red, green and blue channel
+    pub mse: [f64; 3],
+    // Peak signal-to-noise ratio in decibels of each channel, infinite for identical channels
+    pub psnr: [f64; 3],
+    pub ssim: f64,
+    pub ms_ssim: f64,
+    // CIEDE2000 color difference of pixels
+    pub mean_delta_e: f64,
+    pub max_delta_e: f64,
+    pub unique_colors: usize,
+}
+
+// Luminance of every pixel, used by SSIM
+struct LuminancePlane {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+}
+
+impl ImageMetrics {
+    pub fn compute(
+        initial_image: &ColorImage,
+        processed_image: &ColorImage,
+    ) -> Result<ImageMetrics> {
+        if initial_image.size != processed_image.size {
+            bail!(
+                "compared images have different sizes: {}x{} and {}x{}",
+                initial_image.width(),
+                initial_image.height(),
+                processed_image.width(),
+                processed_image.height()
+            );
+        }
+        let initial_pixels = Self::rgb_pixels(initial_image);
+        let processed_pixels = Self::rgb_pixels(processed_image);
+
+        let mse = Self::mse(&initial_pixels, &processed_pixels);
+        let psnr = mse.map(|mse| 10.0 * (255.0 * 255.0 / mse).log10());
+        let (mean_delta_e, max_delta_e) = Self::delta_e(&initial_pixels, &processed_pixels);
+        let initial_plane = LuminancePlane::new(initial_image.size, &initial_pixels);
+        let processed_plane = LuminancePlane::new(processed_image.size, &processed_pixels);
+        // Empty images are identical
+        let (ssim, ms_ssim) = if initial_pixels.is_empty() {
+            (1.0, 1.0)
+        } else {
+            let (ssim, _) = initial_plane.ssim(&processed_plane);
+            (ssim, initial_plane.ms_ssim(processed_plane))
+        };
+
+        Ok(ImageMetrics {
+            mse,
+            psnr,
+            ssim,
+            ms_ssim,
+            mean_delta_e,
+            max_delta_e,
+            unique_colors: Self::unique_colors(processed_image),
+        })
+    }
+
+    fn rgb_pixels(image: &ColorImage) -> Vec<Color32> {
+        image
+            .pixels
+            .par_iter()
+            .map(|pixel| {
+                let [r, g, b, _] = pixel.to_srgba_unmultiplied();
+                Color32::from_rgb(r, g, b)
+            })
+            .collect()
+    }
+
+    fn mse(initial_pixels: &[Color32], processed_pixels: &[Color32]) -> [f64; 3] {
+        let sums = initial_pixels
+            .par_iter()
+            .zip(processed_pixels)
+            .map(|(initial, processed)| {
+                let (initial, processed) = (initial.to_array(), processed.to_array());
+                [0, 1, 2].map(|channel| {
+                    let difference = initial[channel] as f64 - processed[channel] as f64;
+                    difference * difference
+                })
+            })
+            .reduce(
+                || [0.0; 3],
+                |lhs, rhs| [lhs[0] + rhs[0], lhs[1] + rhs[1], lhs[2] + rhs[2]],
+            );
+        let count = initial_pixels.len().max(1) as f64;
+        sums.map(|sum| sum / count)
+    }
+
+    // Returns mean and maximum difference
+    fn delta_e(initial_pixels: &[Color32], processed_pixels: &[Color32]) -> (f64, f64) {
+        let (sum, max) = initial_pixels
+            .par_iter()
+            .zip(processed_pixels)
+            .map(|(&initial, &processed)| {
+                if initial == processed {
+                    return 0.0;
+                }
+                color_spaces::ciede2000(
+                    color_spaces::color_to_lab(initial),
+                    color_spaces::color_to_lab(processed),
+                ) as f64
+            })
+            .fold(
+                || (0.0, 0.0f64),
+                |(sum, max), delta_e| (sum + delta_e, max.max(delta_e)),
+            )
+            .reduce(
+                || (0.0, 0.0),
+                |(lhs_sum, lhs_max), (rhs_sum, rhs_max)| (lhs_sum + rhs_sum, lhs_max.max(rhs_max)),
+            );
+        (sum / initial_pixels.len().max(1) as f64, max)
+    }
+
+    fn unique_colors(image: &ColorImage) -> usize {
+        image
+            .pixels
+            .par_iter()
+            .fold(HashSet::new, |mut colors, &pixel| {
+                colors.insert(pixel);
+                colors
+            })
+            .reduce(HashSet::new, |mut lhs, rhs| {
+                lhs.extend(rhs);
+                lhs
+            })
+            .len()
+    }
+}
+
+impl LuminancePlane {
+    // Rec. 601 luma of gamma-encoded values, like in the reference implementation of SSIM
+    fn new(size: [usize; 2], pixels: &[Color32]) -> LuminancePlane {
+        let values = pixels
+            .par_iter()
+            .map(|pixel| {
+                0.299 * pixel.r() as f64 + 0.587 * pixel.g() as f64 + 0.114 * pixel.b() as f64
+            })
+            .collect();
+        LuminancePlane {
+            width: size[0],
+            height: size[1],
+            values,
+        }
+    }
+
+    // Averages 2x2 blocks, odd last row and column are dropped
+    fn downsample(&self) -> LuminancePlane {
+        let (width, height) = (self.width / 2, self.height / 2);
+        let values = (0..width * height)
+            .into_par_iter()
+            .map(|id| {
+                let (x, y) = (2 * (id % width), 2 * (id / width));
+                let value = |x: usize, y: usize| self.values[y * self.width + x];
+                (value(x, y) + value(x + 1, y) + value(x, y + 1) + value(x + 1, y + 1)) / 4.0
+            })
+            .collect();
+        LuminancePlane {
+            width,
+            height,
+            values,
+        }
+    }
+
+    // Gaussian blur applied separately to rows and columns, pixels outside of the plane repeat the edge
+    fn blur(&self, values: &[f64]) -> Vec<f64> {
+        let weights: Vec<f64> = (0..=2 * SSIM_WINDOW_RADIUS)
+            .map(|i| {
+                let offset = i as f64 - SSIM_WINDOW_RADIUS as f64;
+                (-offset * offset / (2.0 * SSIM_WINDOW_SIGMA * SSIM_WINDOW_SIGMA)).exp()
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let weights: Vec<f64> = weights.iter().map(|weight| weight / total).collect();
+        let convolve = |position: usize, length: usize, value: &dyn Fn(usize) -> f64| {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, weight)| {
+                    let neighbour = (position + i).saturating_sub(SSIM_WINDOW_RADIUS);
+                    weight * value(neighbour.min(length - 1))
+                })
+                .sum::<f64>()
+        };
+        let (width, height) = (self.width, self.height);
+        let horizontal: Vec<f64> = (0..width * height)
+            .into_par_iter()
+            .map(|id| {
+                let (x, y) = (id % width, id / width);
+                convolve(x, width, &|x| values[y * width + x])
+            })
+            .collect();
+        (0..width * height)
+            .into_par_iter()
+            .map(|id| {
+                let (x, y) = (id % width, id / width);
+                convolve(y, height, &|y| horizontal[y * width + x])
+            })
+            .collect()
+    }
+
+    // Returns mean SSIM and mean of its contrast-structure component
+    fn ssim(&self, other: &LuminancePlane) -> (f64, f64) {
+        let products = |lhs: &[f64], rhs: &[f64]| -> Vec<f64> {
+            lhs.par_iter()
+                .zip(rhs)
+                .map(|(lhs, rhs)| lhs * rhs)
+                .collect()
+        };
+        let (x, y) = (&self.values, &other.values);
+        let mean_x = self.blur(x);
+        let mean_y = self.blur(y);
+        let mean_xx = self.blur(&products(x, x));
+        let mean_yy = self.blur(&products(y, y));
+        let mean_xy = self.blur(&products(x, y));
+        let (ssim_sum, cs_sum) = (0..x.len())
+            .into_par_iter()
+            .map(|id| {
+                let (mx, my) = (mean_x[id], mean_y[id]);
+                let variance_x = mean_xx[id] - mx * mx;
+                let variance_y = mean_yy[id] - my * my;
+                let covariance = mean_xy[id] - mx * my;
+                let luminance = (2.0 * mx * my + SSIM_C1) / (mx * mx + my * my + SSIM_C1);
+                let contrast_structure =
+                    (2.0 * covariance + SSIM_C2) / (variance_x + variance_y + SSIM_C2);
+                (luminance * contrast_structure, contrast_structure)
+            })
+            .reduce(
+                || (0.0, 0.0),
+                |(lhs_ssim, lhs_cs), (rhs_ssim, rhs_cs)| (lhs_ssim + rhs_ssim, lhs_cs + rhs_cs),
+            );
+        let count = x.len().max(1) as f64;
+        (ssim_sum / count, cs_sum / count)
+    }
+
+    // Small images use fewer scales, their weights are normalized to sum up to 1
+    fn ms_ssim(self, other: LuminancePlane) -> f64 {
+        let (mut x, mut y) = (self, other);
+        let mut factors = Vec::new();
+        for (scale, &weight) in MS_SSIM_WEIGHTS.iter().enumerate() {
+            let (ssim, contrast_structure) = x.ssim(&y);
+            let last = scale == MS_SSIM_WEIGHTS.len() - 1 || x.width < 2 || x.height < 2;
+            // Luminance is compared only at the coarsest scale
+            let value = if last { ssim } else { contrast_structure };
+            factors.push((value.max(0.0), weight));
+            if last {
+                break;
+            }
+            (x, y) = (x.downsample(), y.downsample());
+        }
+        let total_weight: f64 = factors.iter().map(|(_, weight)| weight).sum();
+        factors
+            .iter()
+            .map(|(value, weight)| value.powf(weight / total_weight))
+            .product()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_image(width: usize, height: usize, offset: u8) -> ColorImage {
+        let pixels: Vec<u8> = (0..width * height)
+            .flat_map(|id| {
+                let (x, y) = (id % width, id / width);
+                [
+                    (x * 200 / width) as u8 + offset,
+                    (y * 200 / height) as u8 + offset,
+                    ((x + y) * 100 / (width + height)) as u8 + offset,
+                    255,
+                ]
+            })
+            .collect();
+        ColorImage::from_rgba_unmultiplied([width, height], &pixels)
+    }
+
+    #[test]
+    fn identical_images_have_no_error() {
+        let image = gradient_image(64, 48, 0);
+        let metrics = ImageMetrics::compute(&image, &image).unwrap();
+        assert_eq!(metrics.mse, [0.0; 3]);
+        assert_eq!(metrics.psnr, [f64::INFINITY; 3]);
+        assert!((metrics.ssim - 1.0).abs() < 1e-9, "{}", metrics.ssim);
+        assert!((metrics.ms_ssim - 1.0).abs() < 1e-9, "{}", metrics.ms_ssim);
+        assert_eq!(metrics.mean_delta_e, 0.0);
+        assert_eq!(metrics.max_delta_e, 0.0);
+    }
+
+    #[test]
+    fn constant_offset_gives_expected_mse_and_psnr() {
+        let initial_image = gradient_image(64, 48, 0);
+        let processed_image = gradient_image(64, 48, 10);
+        let metrics = ImageMetrics::compute(&initial_image, &processed_image).unwrap();
+        let expected_psnr = 10.0 * (255.0_f64 * 255.0 / 100.0).log10();
+        for channel in 0..3 {
+            assert!((metrics.mse[channel] - 100.0).abs() < 1e-9);
+            assert!((metrics.psnr[channel] - expected_psnr).abs() < 1e-9);
+        }
+        assert!(metrics.ssim < 1.0);
+        assert!(metrics.mean_delta_e > 0.0);
+    }
+
+    #[test]
+    fn images_of_different_sizes_are_rejected() {
+        let initial_image = gradient_image(64, 48, 0);
+        let processed_image = gradient_image(48, 64, 0);
+        assert!(ImageMetrics::compute(&initial_image, &processed_image).is_err());
+    }
+
+    #[test]
+    fn empty_images_are_identical() {
+        let image = ColorImage::new([0, 0], Color32::BLACK);
+        let metrics = ImageMetrics::compute(&image, &image).unwrap();
+        assert_eq!(metrics.mse, [0.0; 3]);
+        // No error at all, like for identical images
+        assert_eq!(metrics.psnr, [f64::INFINITY; 3]);
+        assert_eq!(metrics.ssim, 1.0);
+        assert_eq!(metrics.ms_ssim, 1.0);
+        assert_eq!(metrics.mean_delta_e, 0.0);
+        assert_eq!(metrics.max_delta_e, 0.0);
+        assert_eq!(metrics.unique_colors, 0);
+    }
+}
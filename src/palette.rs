@@ -0,0 +1,465 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use anyhow::{bail, Context, Result};
+use egui::{Color32, ColorImage};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{
+    algorithms::{FixedPalette, ImportedPalette, PaletteSource},
+    builtin_palettes,
+    color_quantizers::{
+        FixedPaletteColorQuantizer, KMeansColorQuantizer, MedianCutColorQuantizer,
+        NeuQuantColorQuantizer, OctreeColorQuantizer, PaletteBuilder,
+        PopularityAlgorithmColorQuantizer, WuColorQuantizer,
+    },
+    image_loader,
+};
+
+// Colors chosen by a palette algorithm
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Palette {
+    pub colors: Vec<Color32>,
+}
+
+// File formats palette can be exported to
+#[derive(Debug, Default, EnumIter, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PaletteFormat {
+    #[default]
+    Gimp,
+    AdobeColorTable,
+    AdobeColorSwatch,
+    JascPal,
+    PaintNet,
+    HexList,
+}
+
+impl Display for PaletteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteFormat::Gimp => write!(f, "GIMP Palette"),
+            PaletteFormat::AdobeColorTable => write!(f, "Adobe Color Table"),
+            PaletteFormat::AdobeColorSwatch => write!(f, "Adobe Color Swatch"),
+            PaletteFormat::JascPal => write!(f, "JASC Palette"),
+            PaletteFormat::PaintNet => write!(f, "Paint.NET Palette"),
+            PaletteFormat::HexList => write!(f, "Hex List"),
+        }
+    }
+}
+
+impl PaletteFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Gimp => "gpl",
+            PaletteFormat::AdobeColorTable => "act",
+            PaletteFormat::AdobeColorSwatch => "aco",
+            PaletteFormat::JascPal => "pal",
+            PaletteFormat::PaintNet => "txt",
+            PaletteFormat::HexList => "hex",
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<PaletteFormat> {
+        let extension = path.as_ref().extension()?.to_string_lossy().to_lowercase();
+        PaletteFormat::iter().find(|format| format.extension() == extension)
+    }
+}
+
+impl Palette {
+    // Adobe Color Table always stores this number of colors
+    const ACT_COLORS: usize = 256;
+    // Images used as palettes should contain only a few colors
+    const MAX_IMAGE_COLORS: usize = 256;
+
+    pub fn new(colors: Vec<Color32>) -> Palette {
+        Palette { colors }
+    }
+
+    // Runs the palette algorithm without mapping the image to the palette
+    pub fn build(source: &PaletteSource, initial_image: &ColorImage) -> Palette {
+        match source {
+            PaletteSource::Popularity(params) => {
+                PopularityAlgorithmColorQuantizer::build_palette(*params, initial_image)
+            }
+            PaletteSource::MedianCut(params) => {
+                MedianCutColorQuantizer::build_palette(*params, initial_image)
+            }
+            PaletteSource::Octree(params) => {
+                OctreeColorQuantizer::build_palette(*params, initial_image)
+            }
+            PaletteSource::KMeans(params) => {
+                KMeansColorQuantizer::build_palette(*params, initial_image)
+            }
+            PaletteSource::Wu(params) => WuColorQuantizer::build_palette(*params, initial_image),
+            PaletteSource::NeuQuant(params) => {
+                NeuQuantColorQuantizer::build_palette(*params, initial_image)
+            }
+            PaletteSource::Fixed(params) => {
+                FixedPaletteColorQuantizer::build_palette(params.clone(), initial_image)
+            }
+        }
+    }
+
+    pub fn into_fixed(self) -> FixedPalette {
+        FixedPalette::Imported(ImportedPalette::new(self.colors))
+    }
+
+    pub fn fixed(palette: &FixedPalette) -> Palette {
+        match palette {
+            FixedPalette::Builtin(palette) => Palette::new(builtin_palettes::colors(*palette)),
+            FixedPalette::Imported(palette) => Palette::new(palette.colors().to_vec()),
+        }
+    }
+
+    // Format is deduced from the extension of the path, images are read as palettes consisting of their unique colors
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette> {
+        let path = path.as_ref();
+        let palette = match PaletteFormat::from_path(path) {
+            Some(format) => {
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                Self::decode(&bytes, format)
+            }
+            None => {
+                let image = image_loader::load_image_from_path(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                Self::decode_image(&image)
+            }
+        };
+        palette.with_context(|| format!("invalid palette {}", path.display()))
+    }
+
+    pub fn decode(bytes: &[u8], format: PaletteFormat) -> Result<Palette> {
+        let text = || String::from_utf8_lossy(bytes);
+        let colors = match format {
+            PaletteFormat::Gimp => Self::decode_gimp(&text())?,
+            PaletteFormat::AdobeColorTable => Self::decode_act(bytes)?,
+            PaletteFormat::AdobeColorSwatch => {
+                bail!("importing Adobe Color Swatch is not supported")
+            }
+            PaletteFormat::JascPal => Self::decode_jasc_pal(&text())?,
+            PaletteFormat::PaintNet | PaletteFormat::HexList => Self::decode_hex_list(&text())?,
+        };
+        if colors.is_empty() {
+            bail!("palette has no colors");
+        }
+        Ok(Palette::new(colors))
+    }
+
+    fn decode_image(image: &ColorImage) -> Result<Palette> {
+        // Palette colors are opaque, as algorithms work on images without alpha
+        let opaque_pixels = image.pixels.iter().map(|pixel| {
+            let [r, g, b, _] = pixel.to_srgba_unmultiplied();
+            Color32::from_rgb(r, g, b)
+        });
+        let mut colors = Vec::new();
+        for color in opaque_pixels {
+            if !colors.contains(&color) {
+                if colors.len() == Self::MAX_IMAGE_COLORS {
+                    bail!(
+                        "image used as palette can have at most {} colors",
+                        Self::MAX_IMAGE_COLORS
+                    );
+                }
+                colors.push(color);
+            }
+        }
+        Ok(Palette::new(colors))
+    }
+
+    fn parse_rgb<'a>(mut values: impl Iterator<Item = &'a str>) -> Result<Color32> {
+        let mut channel = || -> Result<u8> {
+            let value = values.next().context("missing color channel")?;
+            value
+                .parse()
+                .with_context(|| format!("invalid color channel {}", value))
+        };
+        Ok(Color32::from_rgb(channel()?, channel()?, channel()?))
+    }
+
+    fn decode_gimp(text: &str) -> Result<Vec<Color32>> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some("GIMP Palette") {
+            bail!("missing GIMP Palette header");
+        }
+        lines
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty()
+                    && !line.starts_with('#')
+                    && !line.starts_with("Name:")
+                    && !line.starts_with("Columns:")
+            })
+            .map(|line| Self::parse_rgb(line.split_whitespace()))
+            .collect()
+    }
+
+    // Files without the count of colors (768 bytes long) always contain 256 colors
+    fn decode_act(bytes: &[u8]) -> Result<Vec<Color32>> {
+        let count = match bytes.len() {
+            768 => Self::ACT_COLORS,
+            772 => u16::from_be_bytes([bytes[768], bytes[769]]) as usize,
+            _ => bail!("Adobe Color Table must be 768 or 772 bytes long"),
+        };
+        if count > Self::ACT_COLORS {
+            bail!("Adobe Color Table can't have {} colors", count);
+        }
+        Ok(bytes[..count * 3]
+            .chunks(3)
+            .map(|rgb| Color32::from_rgb(rgb[0], rgb[1], rgb[2]))
+            .collect())
+    }
+
+    fn decode_jasc_pal(text: &str) -> Result<Vec<Color32>> {
+        let mut lines = text.lines().map(str::trim);
+        if lines.next() != Some("JASC-PAL") {
+            bail!("missing JASC-PAL header");
+        }
+        lines.next().context("missing version")?;
+        let count = lines.next().context("missing number of colors")?;
+        let count: usize = count
+            .parse()
+            .with_context(|| format!("invalid number of colors {}", count))?;
+        let colors = lines
+            .filter(|line| !line.is_empty())
+            .take(count)
+            .map(|line| Self::parse_rgb(line.split_whitespace()))
+            .collect::<Result<Vec<_>>>()?;
+        if colors.len() != count {
+            bail!("expected {} colors, found {}", count, colors.len());
+        }
+        Ok(colors)
+    }
+
+    // Colors are written as `rrggbb` or (in Paint.NET palettes) `aarrggbb`, optionally prefixed with `#`,
+    // lines starting with `;` are comments
+    fn decode_hex_list(text: &str) -> Result<Vec<Color32>> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(';'))
+            .map(|line| {
+                let hex = line.trim_start_matches('#');
+                // Slicing and parsing below expect ASCII digits only, `from_str_radix` would accept a sign
+                if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    bail!("invalid color {}", line);
+                }
+                let rgb = match hex.len() {
+                    6 => hex,
+                    8 => &hex[2..],
+                    _ => bail!("invalid color {}", line),
+                };
+                let value = u32::from_str_radix(rgb, 16)
+                    .with_context(|| format!("invalid color {}", line))?;
+                let [_, r, g, b] = value.to_be_bytes();
+                Ok(Color32::from_rgb(r, g, b))
+            })
+            .collect()
+    }
+
+    // Colors used by the image, the most common ones first
+    pub fn from_image(image: &ColorImage) -> Palette {
+        let mut colors_count = HashMap::<Color32, usize>::new();
+        for pixel in &image.pixels {
+            *colors_count.entry(*pixel).or_insert(0) += 1;
+        }
+        let mut colors: Vec<(Color32, usize)> = colors_count.into_iter().collect();
+        colors.sort_unstable_by_key(|&(color, count)| (std::cmp::Reverse(count), color.to_array()));
+        Palette::new(colors.into_iter().map(|(color, _)| color).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    fn hex(color: Color32) -> String {
+        let [r, g, b, _] = color.to_srgba_unmultiplied();
+        format!("{:02x}{:02x}{:02x}", r, g, b)
+    }
+
+    pub fn encode(&self, format: PaletteFormat) -> Result<Vec<u8>> {
+        let bytes = match format {
+            PaletteFormat::Gimp => self.encode_gimp().into_bytes(),
+            PaletteFormat::AdobeColorTable => self.encode_act()?,
+            PaletteFormat::AdobeColorSwatch => self.encode_aco()?,
+            PaletteFormat::JascPal => self.encode_jasc_pal().into_bytes(),
+            PaletteFormat::PaintNet => self.encode_paint_net().into_bytes(),
+            PaletteFormat::HexList => self.encode_hex_list().into_bytes(),
+        };
+        Ok(bytes)
+    }
+
+    // Format is deduced from the extension of the path
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let Some(format) = PaletteFormat::from_path(path) else {
+            bail!("unsupported palette format of {}", path.display());
+        };
+        let bytes = self.encode(format)?;
+        std::fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn encode_gimp(&self) -> String {
+        let mut text = String::from("GIMP Palette\nName: Color Quantizer\nColumns: 16\n#\n");
+        for &color in &self.colors {
+            let [r, g, b, _] = color.to_srgba_unmultiplied();
+            text += &format!("{:3} {:3} {:3}\t#{}\n", r, g, b, Self::hex(color));
+        }
+        text
+    }
+
+    // 256 RGB triples followed by the number of used colors and the transparent color index (none)
+    fn encode_act(&self) -> Result<Vec<u8>> {
+        if self.len() > Self::ACT_COLORS {
+            bail!(
+                "Adobe Color Table supports at most {} colors, palette has {}",
+                Self::ACT_COLORS,
+                self.len()
+            );
+        }
+        let mut bytes: Vec<u8> = self
+            .colors
+            .iter()
+            .flat_map(|color| {
+                let [r, g, b, _] = color.to_srgba_unmultiplied();
+                [r, g, b]
+            })
+            .collect();
+        bytes.resize(Self::ACT_COLORS * 3, 0);
+        bytes.extend_from_slice(&(self.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&u16::MAX.to_be_bytes());
+        Ok(bytes)
+    }
+
+    // Version 1 section is followed by version 2 section, which adds color names
+    fn encode_aco(&self) -> Result<Vec<u8>> {
+        let Ok(count) = u16::try_from(self.len()) else {
+            bail!(
+                "Adobe Color Swatch supports at most {} colors, palette has {}",
+                u16::MAX,
+                self.len()
+            );
+        };
+        let color_record = |color: Color32| {
+            let [r, g, b, _] = color.to_srgba_unmultiplied();
+            // Color space 0 is RGB, channels are scaled to 16 bits and the fourth one is unused
+            [0, r as u16 * 257, g as u16 * 257, b as u16 * 257, 0].map(u16::to_be_bytes)
+        };
+        let mut bytes = Vec::new();
+        for version in [1u16, 2] {
+            bytes.extend_from_slice(&version.to_be_bytes());
+            bytes.extend_from_slice(&count.to_be_bytes());
+            for &color in &self.colors {
+                bytes.extend(color_record(color).concat());
+                if version == 2 {
+                    // UTF-16 name with its length, both including terminating zero
+                    let name: Vec<u16> = format!("#{}", Self::hex(color))
+                        .encode_utf16()
+                        .chain([0])
+                        .collect();
+                    bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+                    bytes.extend(name.iter().flat_map(|unit| unit.to_be_bytes()));
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn encode_jasc_pal(&self) -> String {
+        let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", self.len());
+        for color in &self.colors {
+            let [r, g, b, _] = color.to_srgba_unmultiplied();
+            text += &format!("{} {} {}\r\n", r, g, b);
+        }
+        text
+    }
+
+    fn encode_paint_net(&self) -> String {
+        let mut text = String::from(
+            "; paint.net Palette File\n; Colors are written as 8-digit hexadecimal numbers: aarrggbb\n",
+        );
+        for &color in &self.colors {
+            let alpha = color.to_srgba_unmultiplied()[3];
+            text += &format!("{:02X}{}\n", alpha, Self::hex(color).to_uppercase());
+        }
+        text
+    }
+
+    fn encode_hex_list(&self) -> String {
+        self.colors
+            .iter()
+            .map(|&color| Self::hex(color) + "\n")
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette {
+        Palette::new(vec![
+            Color32::from_rgb(0, 0, 0),
+            Color32::from_rgb(255, 255, 255),
+            Color32::from_rgb(18, 52, 86),
+            Color32::from_rgb(171, 205, 239),
+            Color32::from_rgb(1, 128, 254),
+        ])
+    }
+
+    #[test]
+    fn palettes_survive_round_trip() {
+        let palette = palette();
+        for format in PaletteFormat::iter() {
+            if format == PaletteFormat::AdobeColorSwatch {
+                continue;
+            }
+            let bytes = palette.encode(format).unwrap();
+            let decoded = Palette::decode(&bytes, format).unwrap();
+            assert_eq!(decoded, palette, "{}", format);
+        }
+    }
+
+    #[test]
+    fn adobe_color_swatch_contains_both_versions() {
+        let palette = palette();
+        let bytes = palette.encode(PaletteFormat::AdobeColorSwatch).unwrap();
+        // Version 1 header and records, then version 2 header and records with names "#rrggbb"
+        let version_1_len = 4 + palette.len() * 10;
+        let version_2_len = 4 + palette.len() * (10 + 4 + 8 * 2);
+        assert_eq!(bytes.len(), version_1_len + version_2_len);
+        assert_eq!(bytes[..4], [0, 1, 0, 5]);
+        assert_eq!(bytes[version_1_len..version_1_len + 4], [0, 2, 0, 5]);
+        // Third color in RGB color space with channels scaled to 16 bits
+        assert_eq!(
+            bytes[4 + 2 * 10..4 + 3 * 10],
+            [0, 0, 0x12, 0x12, 0x34, 0x34, 0x56, 0x56, 0, 0]
+        );
+        assert!(Palette::decode(&bytes, PaletteFormat::AdobeColorSwatch).is_err());
+    }
+
+    #[test]
+    fn hex_list_rejects_malformed_colors() {
+        for text in [
+            "12345", "1234567", "+12345", "-1234567", "12345g", "#12 345", "ab cd ef",
+        ] {
+            assert!(
+                Palette::decode(text.as_bytes(), PaletteFormat::HexList).is_err(),
+                "{}",
+                text
+            );
+        }
+        let palette =
+            Palette::decode(b"; comment\n#123456\n\nFFabcdef\n", PaletteFormat::HexList).unwrap();
+        assert_eq!(
+            palette.colors,
+            vec![
+                Color32::from_rgb(0x12, 0x34, 0x56),
+                Color32::from_rgb(0xab, 0xcd, 0xef)
+            ]
+        );
+    }
+}
@@ -6,14 +6,25 @@ use lru::LruCache;
 use crate::{
     algorithms::{Algorithm, AlgorithmCacheKey, AlgorithmParameters},
     color_quantizers::{
-        AverageDitheringColorQuantizer, ColorQuantizer, ErrorDiffusionDitheringColorQuantizer,
+        AlphaQuantizer, AverageDitheringColorQuantizer, ColorQuantizer,
+        ErrorDiffusionDitheringColorQuantizer, FixedPaletteColorQuantizer, HalftoneColorQuantizer,
+        KMeansColorQuantizer, MedianCutColorQuantizer, NeuQuantColorQuantizer,
+        OctreeColorQuantizer, OrderedDitheringBlueNoiseColorQuantizer,
         OrderedDitheringRandomColorQuantizer, OrderedDitheringRelativeColorQuantizer,
-        PopularityAlgorithmColorQuantizer,
+        PatternDitheringColorQuantizer, PopularityAlgorithmColorQuantizer,
+        RiemersmaDitheringColorQuantizer, WuColorQuantizer,
     },
+    metrics::ImageMetrics,
 };
 
 pub struct ProcessedImagesCache {
-    cache: LruCache<AlgorithmCacheKey, ColorImage>,
+    cache: LruCache<AlgorithmCacheKey, ProcessedImage>,
+}
+
+// Processed image together with its comparison with the initial image, computed only once per image
+pub struct ProcessedImage {
+    pub image: ColorImage,
+    pub metrics: Option<ImageMetrics>,
 }
 
 impl ProcessedImagesCache {
@@ -24,52 +35,154 @@ impl ProcessedImagesCache {
 
     // Returns image for given algorithm and parameters
     // If no image match provided criteria, new image is created with proper algorithm
-    pub fn get(&mut self, key: AlgorithmCacheKey, initial_image: &ColorImage) -> &ColorImage {
-        self.cache
-            .get_or_insert(key, || Self::create_new_image(&key, initial_image))
+    pub fn get(&mut self, key: AlgorithmCacheKey, initial_image: &ColorImage) -> &ProcessedImage {
+        self.cache.get_or_insert(key.clone(), || {
+            let image = Self::create_new_image(&key, initial_image);
+            let metrics = ImageMetrics::compute(initial_image, &image).ok();
+            ProcessedImage { image, metrics }
+        })
     }
 
     pub fn clear(&mut self) {
         self.cache.clear();
     }
 
-    fn create_new_image(key: &AlgorithmCacheKey, initial_image: &ColorImage) -> ColorImage {
+    // Processes image with given algorithm and parameters, bypassing the cache
+    pub fn create_new_image(key: &AlgorithmCacheKey, initial_image: &ColorImage) -> ColorImage {
+        let alpha = AlphaQuantizer::quantize_alpha(key.alpha, initial_image);
+        let opaque_image = AlphaQuantizer::opaque_image(initial_image, &alpha);
+        let processed_image = Self::create_new_opaque_image(key, &opaque_image);
+        AlphaQuantizer::apply_alpha(key.alpha, &processed_image, &alpha)
+    }
+
+    fn create_new_opaque_image(key: &AlgorithmCacheKey, initial_image: &ColorImage) -> ColorImage {
         match key.algorithm {
             Algorithm::AverageDithering => {
-                let params = match key.params {
-                    AlgorithmParameters::Dithering(dithering_parameters) => dithering_parameters,
-                    AlgorithmParameters::Popularity(_) => panic!("UNREACHABLE"),
+                let params = match &key.params {
+                    AlgorithmParameters::Dithering(dithering_parameters) => *dithering_parameters,
+                    _ => panic!("UNREACHABLE"),
                 };
                 AverageDitheringColorQuantizer::generate_output_image(params, initial_image)
             }
             Algorithm::ErrorDiffusionDithering => {
-                let params = match key.params {
-                    AlgorithmParameters::Dithering(dithering_parameters) => dithering_parameters,
-                    AlgorithmParameters::Popularity(_) => panic!("UNREACHABLE"),
+                let params = match &key.params {
+                    AlgorithmParameters::ErrorDiffusion(error_diffusion_parameters) => {
+                        error_diffusion_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
                 };
                 ErrorDiffusionDitheringColorQuantizer::generate_output_image(params, initial_image)
             }
+            Algorithm::RiemersmaDithering => {
+                let params = match &key.params {
+                    AlgorithmParameters::Riemersma(riemersma_parameters) => {
+                        riemersma_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
+                };
+                RiemersmaDitheringColorQuantizer::generate_output_image(params, initial_image)
+            }
             Algorithm::OrderedDitheringRandom => {
-                let params = match key.params {
-                    AlgorithmParameters::Dithering(dithering_parameters) => dithering_parameters,
-                    AlgorithmParameters::Popularity(_) => panic!("UNREACHABLE"),
+                let params = match &key.params {
+                    AlgorithmParameters::RandomOrderedDithering(
+                        random_ordered_dithering_parameters,
+                    ) => random_ordered_dithering_parameters.clone(),
+                    _ => panic!("UNREACHABLE"),
                 };
                 OrderedDitheringRandomColorQuantizer::generate_output_image(params, initial_image)
             }
             Algorithm::OrderedDitheringRelative => {
-                let params = match key.params {
-                    AlgorithmParameters::Dithering(dithering_parameters) => dithering_parameters,
-                    AlgorithmParameters::Popularity(_) => panic!("UNREACHABLE"),
+                let params = match &key.params {
+                    AlgorithmParameters::OrderedDithering(ordered_dithering_parameters) => {
+                        ordered_dithering_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
                 };
                 OrderedDitheringRelativeColorQuantizer::generate_output_image(params, initial_image)
             }
+            Algorithm::OrderedDitheringBlueNoise => {
+                let params = match &key.params {
+                    AlgorithmParameters::BlueNoiseDithering(blue_noise_dithering_parameters) => {
+                        blue_noise_dithering_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
+                };
+                OrderedDitheringBlueNoiseColorQuantizer::generate_output_image(
+                    params,
+                    initial_image,
+                )
+            }
+            Algorithm::Halftone => {
+                let params = match &key.params {
+                    AlgorithmParameters::Halftone(halftone_parameters) => {
+                        halftone_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
+                };
+                HalftoneColorQuantizer::generate_output_image(params, initial_image)
+            }
+            Algorithm::PatternDithering => {
+                let params = match &key.params {
+                    AlgorithmParameters::PatternDithering(pattern_dithering_parameters) => {
+                        pattern_dithering_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
+                };
+                PatternDitheringColorQuantizer::generate_output_image(params, initial_image)
+            }
             Algorithm::PopularityAlgorithm => {
-                let params = match key.params {
-                    AlgorithmParameters::Dithering(_) => panic!("UNREACHABLE"),
-                    AlgorithmParameters::Popularity(popularity_parameters) => popularity_parameters,
+                let params = match &key.params {
+                    AlgorithmParameters::Popularity(popularity_parameters) => {
+                        *popularity_parameters
+                    }
+                    _ => panic!("UNREACHABLE"),
                 };
                 PopularityAlgorithmColorQuantizer::generate_output_image(params, initial_image)
             }
+            Algorithm::MedianCut => {
+                let params = match &key.params {
+                    AlgorithmParameters::MedianCut(median_cut_parameters) => *median_cut_parameters,
+                    _ => panic!("UNREACHABLE"),
+                };
+                MedianCutColorQuantizer::generate_output_image(params, initial_image)
+            }
+            Algorithm::Octree => {
+                let params = match &key.params {
+                    AlgorithmParameters::Octree(octree_parameters) => *octree_parameters,
+                    _ => panic!("UNREACHABLE"),
+                };
+                OctreeColorQuantizer::generate_output_image(params, initial_image)
+            }
+            Algorithm::KMeans => {
+                let params = match &key.params {
+                    AlgorithmParameters::KMeans(k_means_parameters) => *k_means_parameters,
+                    _ => panic!("UNREACHABLE"),
+                };
+                KMeansColorQuantizer::generate_output_image(params, initial_image)
+            }
+            Algorithm::Wu => {
+                let params = match &key.params {
+                    AlgorithmParameters::Wu(wu_parameters) => *wu_parameters,
+                    _ => panic!("UNREACHABLE"),
+                };
+                WuColorQuantizer::generate_output_image(params, initial_image)
+            }
+            Algorithm::NeuQuant => {
+                let params = match &key.params {
+                    AlgorithmParameters::NeuQuant(neu_quant_parameters) => *neu_quant_parameters,
+                    _ => panic!("UNREACHABLE"),
+                };
+                NeuQuantColorQuantizer::generate_output_image(params, initial_image)
+            }
+            Algorithm::FixedPalette => {
+                let params = match &key.params {
+                    AlgorithmParameters::FixedPalette(fixed_palette_parameters) => {
+                        fixed_palette_parameters.clone()
+                    }
+                    _ => panic!("UNREACHABLE"),
+                };
+                FixedPaletteColorQuantizer::generate_output_image(params, initial_image)
+            }
         }
     }
 }